@@ -1,8 +1,11 @@
 use std::ops::Range;
 
 use mlua::{Lua, MetaMethod, ToLua, UserData, Value};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
 pub struct Pos {
     x: i64,
     y: i64,
@@ -17,7 +20,7 @@ pub struct PosIterator {
     range: Pos,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BlockFace {
     XP,
     XN,
@@ -27,7 +30,7 @@ pub enum BlockFace {
     ZN,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BlockVisibility {
     Empty,
     Transparent,
@@ -68,6 +71,15 @@ impl Pos {
         (self.x as f32, self.y as f32, self.z as f32)
     }
 
+    /// [x, y, z]
+    pub fn to_array(self) -> [i64; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    pub fn from_array(a: [i64; 3]) -> Pos {
+        Pos::from_xyz(a[0], a[1], a[2])
+    }
+
     pub fn from_xyz(x: i64, y: i64, z: i64) -> Pos {
         Pos { x, y, z }
     }
@@ -114,6 +126,34 @@ impl BlockFace {
         ]
         .into_iter()
     }
+
+    /// the index of this face in `iter_all`
+    pub fn to_index(self) -> usize {
+        match self {
+            BlockFace::XP => 0,
+            BlockFace::XN => 1,
+            BlockFace::YP => 2,
+            BlockFace::YN => 3,
+            BlockFace::ZP => 4,
+            BlockFace::ZN => 5,
+        }
+    }
+
+    pub fn opposite(self) -> BlockFace {
+        match self {
+            BlockFace::XP => BlockFace::XN,
+            BlockFace::XN => BlockFace::XP,
+            BlockFace::YP => BlockFace::YN,
+            BlockFace::YN => BlockFace::YP,
+            BlockFace::ZP => BlockFace::ZN,
+            BlockFace::ZN => BlockFace::ZP,
+        }
+    }
+
+    /// the unit vector this face points to
+    pub fn to_pos(self) -> Pos {
+        Pos::default() + self
+    }
 }
 
 impl Iterator for PosIterator {
@@ -459,3 +499,44 @@ fn test_lua() {
         Pos::from_xyz(5, 6, 1)
     );
 }
+
+#[test]
+fn test_serde() {
+    use crate::chunk::{
+        blocks::{BlockId, BlockType, IdMapping},
+        chunk::Seed,
+        generator_plugin::GeneratorInfo,
+    };
+
+    let p = Pos::from_xyz(-1, 2, -3);
+    assert_eq!(
+        serde_json::to_string(&p).unwrap(),
+        r#"{"x":-1,"y":2,"z":-3}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<Pos>(r#"{"x":-1,"y":2,"z":-3}"#).unwrap(),
+        p
+    );
+    assert_eq!(serde_json::to_string(&BlockFace::ZN).unwrap(), r#""ZN""#);
+    assert_eq!(serde_json::to_string(&BlockId::from(7_u64)).unwrap(), "7");
+
+    let id_mapping = IdMapping::default();
+    let json = serde_json::to_string(&id_mapping).unwrap();
+    assert_eq!(
+        serde_json::from_str::<IdMapping>(&json).unwrap(),
+        id_mapping
+    );
+    assert_eq!(
+        serde_json::from_str::<BlockType>(&serde_json::to_string(&BlockType::None).unwrap())
+            .unwrap(),
+        BlockType::None
+    );
+    assert_eq!(
+        serde_json::from_str::<Seed>(r#"{"seed":5}"#).unwrap(),
+        Seed { seed: 5 }
+    );
+
+    let info = GeneratorInfo::default();
+    let json = serde_json::to_string(&info).unwrap();
+    assert_eq!(serde_json::from_str::<GeneratorInfo>(&json).unwrap(), info);
+}
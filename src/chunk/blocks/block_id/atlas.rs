@@ -0,0 +1,412 @@
+use std::{fs, io, path::Path};
+
+use bevy::{
+    prelude::{warn, Image, Resource},
+    render::{
+        render_resource::{
+            Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureFormat,
+        },
+        texture::{CompressedImageFormats, ImageSampler, ImageType},
+    },
+};
+
+use super::BlockRegistry;
+
+/// tiles along each side of a `BlockAtlas`
+pub const ATLAS_TILES: u16 = 8;
+/// the padding on each side of a texture is its size divided by this
+const PADDING_DIVISOR: u32 = 8;
+
+/// the tile of the texture of `Stone`
+pub const STONE_TILE: u16 = 0;
+/// the tile of the texture of `Missing`
+pub const MISSING_TILE: u16 = 1;
+/// the texture `i` of a `BlockRegistry` is in the tile `BUILTIN_TILES + i`
+pub const BUILTIN_TILES: u16 = 2;
+/// the texture names of the built in tiles
+const BUILTIN_TEXTURES: [&str; BUILTIN_TILES as usize] = ["stone", "missing"];
+/// the texture of `Missing`, its placeholder is not the one of textures without a file
+/// so unknown blocks do not look like other blocks with a missing texture
+const MISSING_TEXTURE: &str = BUILTIN_TEXTURES[MISSING_TILE as usize];
+
+/// the size of the textures when there is no texture file to take it from
+const DEFAULT_TILE_SIZE: u32 = 16;
+
+/// the uv rectangle of a tile of the atlas, without its padding
+///
+/// tiles go along u first, then along v
+pub fn tile_uv(tile: u16) -> ((f32, f32), (f32, f32)) {
+    let (col, row) = ((tile % ATLAS_TILES) as f32, (tile / ATLAS_TILES) as f32);
+    let tiles = ATLAS_TILES as f32;
+    // the padding takes 1 / (divisor + 2) of each side of a cell
+    let pad = 1. / (PADDING_DIVISOR + 2) as f32;
+    (
+        ((col + pad) / tiles, (row + pad) / tiles),
+        ((col + 1. - pad) / tiles, (row + 1. - pad) / tiles),
+    )
+}
+
+/// one texture of the PBR material, `BlockAtlas` packs one image for each
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureChannel {
+    BaseColor,
+    Normal,
+    MetallicRoughness,
+    Occlusion,
+    Emissive,
+}
+
+impl TextureChannel {
+    pub const ALL: [TextureChannel; 5] = [
+        TextureChannel::BaseColor,
+        TextureChannel::Normal,
+        TextureChannel::MetallicRoughness,
+        TextureChannel::Occlusion,
+        TextureChannel::Emissive,
+    ];
+
+    pub fn to_index(self) -> usize {
+        self as usize
+    }
+
+    /// the file of the texture `name` in this channel is `{name}{suffix}.png`
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TextureChannel::BaseColor => "",
+            TextureChannel::Normal => "_normal",
+            TextureChannel::MetallicRoughness => "_metallic_roughness",
+            TextureChannel::Occlusion => "_occlusion",
+            TextureChannel::Emissive => "_emissive",
+        }
+    }
+
+    /// the pixel of textures that have no file in this channel
+    ///
+    /// a flat normal, rough and not metallic, not occluded and not glowing
+    pub fn default_pixel(self) -> [u8; 4] {
+        match self {
+            TextureChannel::BaseColor => [255, 255, 255, 255],
+            TextureChannel::Normal => [128, 128, 255, 255],
+            TextureChannel::MetallicRoughness => [0, 255, 0, 255],
+            TextureChannel::Occlusion => [255, 255, 255, 255],
+            TextureChannel::Emissive => [0, 0, 0, 255],
+        }
+    }
+
+    fn format(self) -> TextureFormat {
+        match self {
+            TextureChannel::BaseColor | TextureChannel::Emissive => TextureFormat::Rgba8UnormSrgb,
+            _ => TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// the textures of the built in blocks and of a `BlockRegistry`, packed into one image per `TextureChannel`
+///
+/// every texture is square and has the same size, it gets the tile `tile_uv` expects,
+/// with its border pixels repeated into the padding around it so filtering does not bleed
+#[derive(Debug, Clone, Resource)]
+pub struct BlockAtlas {
+    tile_size: u32,
+    /// by `TextureChannel::to_index`
+    images: Vec<Image>,
+}
+
+/// placeholder textures for every tile
+impl Default for BlockAtlas {
+    fn default() -> Self {
+        BlockAtlas::from_textures(&BUILTIN_TEXTURES, |_, _| Ok(None)).unwrap()
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// a magenta and black checkerboard, so textures that are not found stand out
+fn checkerboard(size: u32) -> Vec<u8> {
+    let check = (size / 2).max(1);
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            data.extend(if (x / check + y / check) % 2 == 1 {
+                [0, 0, 0, 255]
+            } else {
+                [255, 0, 255, 255]
+            });
+        }
+    }
+    data
+}
+
+/// yellow and black diagonal stripes, the placeholder of `Missing` blocks
+fn stripes(size: u32) -> Vec<u8> {
+    let width = (size / 4).max(1);
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            data.extend(if (x + y) / width % 2 == 1 {
+                [0, 0, 0, 255]
+            } else {
+                [255, 210, 0, 255]
+            });
+        }
+    }
+    data
+}
+
+impl BlockAtlas {
+    /// pack the textures `names`, the texture `i` goes into the tile `i`
+    ///
+    /// `load(name, channel)` gives the texture or `None` if it has no file,
+    /// a base color without a file is a checkerboard, or stripes for the texture of `Missing`,
+    /// and the other channels use `TextureChannel::default_pixel`
+    pub fn from_textures<F>(names: &[&str], mut load: F) -> io::Result<BlockAtlas>
+    where
+        F: FnMut(&str, TextureChannel) -> io::Result<Option<Image>>,
+    {
+        if names.len() > (ATLAS_TILES as usize).pow(2) {
+            return Err(invalid_data(format!(
+                "{} textures do not fit into the atlas",
+                names.len()
+            )));
+        }
+
+        // rgba8 pixels by channel, then by texture
+        let mut textures = vec![Vec::with_capacity(names.len()); TextureChannel::ALL.len()];
+        let mut tile_size = None;
+        for name in names {
+            for channel in TextureChannel::ALL {
+                let image = match load(name, channel)? {
+                    Some(v) => v,
+                    None => {
+                        textures[channel.to_index()].push(None);
+                        continue;
+                    }
+                };
+                let size = image.texture_descriptor.size;
+                if size.width != size.height || *tile_size.get_or_insert(size.width) != size.width {
+                    return Err(invalid_data(format!(
+                        "the {channel:?} texture of {name} is {}x{}, textures have to be square and of the same size",
+                        size.width, size.height
+                    )));
+                }
+                let data = image
+                    .convert(TextureFormat::Rgba8UnormSrgb)
+                    .ok_or_else(|| {
+                        invalid_data(format!(
+                            "the {channel:?} texture of {name} has an unsupported format"
+                        ))
+                    })?
+                    .data;
+                textures[channel.to_index()].push(Some(data));
+            }
+        }
+
+        let tile_size = tile_size.unwrap_or(DEFAULT_TILE_SIZE);
+        if tile_size < PADDING_DIVISOR || tile_size % PADDING_DIVISOR != 0 {
+            return Err(invalid_data(format!(
+                "the texture size {tile_size} is not a multiple of {PADDING_DIVISOR}"
+            )));
+        }
+        let images = TextureChannel::ALL
+            .into_iter()
+            .zip(textures)
+            .map(|(channel, textures)| {
+                let (placeholder, missing) = match channel {
+                    TextureChannel::BaseColor => (checkerboard(tile_size), stripes(tile_size)),
+                    _ => {
+                        let pixels = channel
+                            .default_pixel()
+                            .repeat((tile_size * tile_size) as usize);
+                        (pixels.clone(), pixels)
+                    }
+                };
+                let tiles = textures.iter().zip(names).map(|(t, name)| match t {
+                    Some(t) => t.as_slice(),
+                    None if *name == MISSING_TEXTURE => &missing,
+                    None => &placeholder,
+                });
+                pack(tiles, tile_size, channel)
+            })
+            .collect();
+        Ok(BlockAtlas { tile_size, images })
+    }
+
+    /// pack the built in textures and the textures of `registry` from the png files in `dir`,
+    /// see `TextureChannel::suffix`
+    pub fn load(dir: impl AsRef<Path>, registry: &BlockRegistry) -> io::Result<BlockAtlas> {
+        let dir = dir.as_ref();
+        let names = BUILTIN_TEXTURES
+            .into_iter()
+            .chain(registry.textures().iter().map(|s| s.as_str()))
+            .collect::<Vec<_>>();
+        BlockAtlas::from_textures(&names, |name, channel| {
+            let path = dir.join(format!("{name}{}.png", channel.suffix()));
+            let bytes = match fs::read(&path) {
+                Ok(v) => v,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    let builtin = BUILTIN_TEXTURES.contains(&name);
+                    if channel == TextureChannel::BaseColor && !builtin {
+                        warn!("no texture {}, using a placeholder", path.display());
+                    }
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            };
+            Image::from_buffer(
+                &bytes,
+                ImageType::Extension("png"),
+                CompressedImageFormats::NONE,
+                true,
+            )
+            .map(Some)
+            .map_err(|e| invalid_data(format!("{}: {e}", path.display())))
+        })
+    }
+
+    /// the size of every texture in pixels
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    pub fn image(&self, channel: TextureChannel) -> &Image {
+        &self.images[channel.to_index()]
+    }
+}
+
+/// copy every texture into its tile, the texels next to a tile repeat its border
+fn pack<'a>(
+    textures: impl Iterator<Item = &'a [u8]>,
+    tile_size: u32,
+    channel: TextureChannel,
+) -> Image {
+    let pad = tile_size / PADDING_DIVISOR;
+    let cell = tile_size + 2 * pad;
+    let side = cell * ATLAS_TILES as u32;
+    let mut data = vec![0; (side * side * 4) as usize];
+    for (tile, texture) in textures.enumerate() {
+        let x0 = (tile as u32 % ATLAS_TILES as u32) * cell;
+        let y0 = (tile as u32 / ATLAS_TILES as u32) * cell;
+        for y in 0..cell {
+            let ty = y.saturating_sub(pad).min(tile_size - 1);
+            for x in 0..cell {
+                let tx = x.saturating_sub(pad).min(tile_size - 1);
+                let from = ((ty * tile_size + tx) * 4) as usize;
+                let to = (((y0 + y) * side + x0 + x) * 4) as usize;
+                data[to..to + 4].copy_from_slice(&texture[from..from + 4]);
+            }
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: side,
+            height: side,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        channel.format(),
+    );
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Linear,
+        ..Default::default()
+    });
+    image
+}
+
+#[test]
+fn test_block_atlas() {
+    let red = Image::new_fill(
+        Extent3d {
+            width: 8,
+            height: 8,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[255, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    let atlas = BlockAtlas::from_textures(&["stone", "missing", "red"], |name, channel| {
+        Ok((name == "red" && channel != TextureChannel::Normal).then(|| red.clone()))
+    })
+    .unwrap();
+    assert_eq!(atlas.tile_size(), 8);
+
+    // the pixel at the uv, and the padding pixel just outside of it
+    let pixel_at = |channel: TextureChannel, (u, v): (f32, f32), dx: i32| {
+        let image = atlas.image(channel);
+        let side = image.texture_descriptor.size.width as f32;
+        let x = (u * side) as i32 + dx;
+        let y = (v * side) as i32;
+        let i = ((y * side as i32 + x) * 4) as usize;
+        <[u8; 4]>::try_from(&image.data[i..i + 4]).unwrap()
+    };
+    let (min, max) = tile_uv(BUILTIN_TILES);
+    for channel in [TextureChannel::BaseColor, TextureChannel::Emissive] {
+        assert_eq!(pixel_at(channel, min, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel_at(channel, min, -1), [255, 0, 0, 255]);
+    }
+    assert_eq!(
+        pixel_at(TextureChannel::Normal, min, 0),
+        TextureChannel::Normal.default_pixel()
+    );
+    // the last pixel of the tile is just below `max`
+    assert_eq!(
+        pixel_at(TextureChannel::BaseColor, (max.0, min.1), -1),
+        [255, 0, 0, 255]
+    );
+    // textures without a file are a checkerboard, unknown blocks look different from them
+    assert_eq!(
+        pixel_at(TextureChannel::BaseColor, tile_uv(STONE_TILE).0, 0),
+        [255, 0, 255, 255]
+    );
+    assert_eq!(
+        pixel_at(TextureChannel::BaseColor, tile_uv(MISSING_TILE).0, 0),
+        [255, 210, 0, 255]
+    );
+    let base = atlas.image(TextureChannel::BaseColor).clone();
+    let tile_pixels = |tile| {
+        let (min, _) = tile_uv(tile);
+        let side = base.texture_descriptor.size.width as f32;
+        let (x, y) = ((min.0 * side) as usize, (min.1 * side) as usize);
+        (0..8)
+            .flat_map(|dy| {
+                let i = ((y + dy) * side as usize + x) * 4;
+                base.data[i..i + 8 * 4].to_vec()
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_ne!(tile_pixels(STONE_TILE), tile_pixels(MISSING_TILE));
+    for channel in TextureChannel::ALL {
+        let image = atlas.image(channel);
+        assert_eq!(image.texture_descriptor.size.width, 10 * ATLAS_TILES as u32);
+        assert_eq!(image.texture_descriptor.format, channel.format());
+    }
+
+    // tiles do not overlap
+    let (a, b) = (tile_uv(ATLAS_TILES - 1), tile_uv(ATLAS_TILES));
+    assert!(a.1 .0 <= 1. && b.0 .0 < a.0 .0 && b.0 .1 >= a.1 .1);
+
+    let wrong_size = BlockAtlas::from_textures(&["a", "b"], |name, _| {
+        let mut image = red.clone();
+        if name == "b" {
+            image.resize(Extent3d {
+                width: 16,
+                height: 16,
+                depth_or_array_layers: 1,
+            });
+        }
+        Ok(Some(image))
+    });
+    assert!(wrong_size.is_err());
+}
+
+#[test]
+fn test_block_atlas_files() {
+    let registry = BlockRegistry::load("assets/blocks.json").unwrap();
+    let atlas = BlockAtlas::load("assets/textures", &registry).unwrap();
+    assert_eq!(atlas.tile_size(), 128);
+}
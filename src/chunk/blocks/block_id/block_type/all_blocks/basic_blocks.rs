@@ -1,6 +1,11 @@
-use crate::chunk::{blocks::block_id::block_type::BlockClient, BlockVisibility};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use crate::chunk::{
+    blocks::{block_id::block_type::BlockClient, tile_uv, MISSING_TILE, STONE_TILE},
+    BlockVisibility,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Stone;
 
 impl BlockClient for Stone {
@@ -9,6 +14,20 @@ impl BlockClient for Stone {
     }
 
     fn get_uv(self, _face: crate::chunk::BlockFace) -> ((f32, f32), (f32, f32)) {
-        ((0., 0.), (1., 1.))
+        tile_uv(STONE_TILE)
+    }
+}
+
+/// stands in for a saved block that is not in the registry any more
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Missing;
+
+impl BlockClient for Missing {
+    fn visibility(self) -> BlockVisibility {
+        BlockVisibility::Opaque
+    }
+
+    fn get_uv(self, _face: crate::chunk::BlockFace) -> ((f32, f32), (f32, f32)) {
+        tile_uv(MISSING_TILE)
     }
 }
@@ -4,12 +4,20 @@ pub use all_blocks::*;
 mod client;
 pub use client::*;
 
+use serde::{Deserialize, Serialize};
+
 use crate::chunk::BlockVisibility;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use super::RegisteredBlock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BlockType {
     None,
     Stone(Stone),
+    /// a saved block that is not in the registry
+    Missing(Missing),
+    /// defined in a `BlockRegistry`
+    Registered(RegisteredBlock),
 }
 
 impl Default for BlockType {
@@ -18,11 +26,28 @@ impl Default for BlockType {
     }
 }
 
+impl BlockType {
+    /// if the face of this block towards `neighbour` is drawn
+    ///
+    /// only opaque blocks hide the faces next to them,
+    /// but the faces between two transparent blocks of the same type are hidden too
+    pub fn shows_face_to(self, neighbour: BlockType) -> bool {
+        match (self.visibility(), neighbour.visibility()) {
+            (_, BlockVisibility::Opaque) => false,
+            (BlockVisibility::Opaque, _) => true,
+            (BlockVisibility::Transparent, _) => self != neighbour,
+            _ => false,
+        }
+    }
+}
+
 impl BlockClient for BlockType {
     fn visibility(self) -> BlockVisibility {
         match self {
             BlockType::None => BlockVisibility::Empty,
             BlockType::Stone(s) => s.visibility(),
+            BlockType::Missing(m) => m.visibility(),
+            BlockType::Registered(b) => b.visibility(),
         }
     }
 
@@ -30,6 +55,15 @@ impl BlockClient for BlockType {
         match self {
             BlockType::None => ((0., 0.), (0., 0.)),
             BlockType::Stone(s) => s.get_uv(face),
+            BlockType::Missing(m) => m.get_uv(face),
+            BlockType::Registered(b) => b.get_uv(face),
+        }
+    }
+
+    fn emission(self) -> u8 {
+        match self {
+            BlockType::Registered(b) => b.emission(),
+            _ => 0,
         }
     }
 }
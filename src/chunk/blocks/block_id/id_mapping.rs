@@ -1,10 +1,14 @@
 use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
 
-use super::{BlockType, Stone};
+use super::{BlockId, BlockModel, BlockType, Missing, Stone};
 
-#[derive(Debug, Clone, PartialEq, Eq, Resource)]
+#[derive(Debug, Clone, PartialEq, Eq, Resource, Serialize, Deserialize)]
 pub struct IdMapping {
     pub mapping: Vec<BlockType>,
+    /// indexed by `RegisteredBlock::model`
+    #[serde(default)]
+    pub models: Vec<BlockModel>,
 }
 
 impl IdMapping {
@@ -14,12 +18,32 @@ impl IdMapping {
             None => None,
         }
     }
+
+    /// the first id of a block type
+    pub fn id_of(&self, block: BlockType) -> Option<BlockId> {
+        self.mapping
+            .iter()
+            .position(|v| *v == block)
+            .map(BlockId::from)
+    }
+
+    pub fn model_of(&self, block: BlockType) -> Option<&BlockModel> {
+        match block {
+            BlockType::Registered(b) => self.models.get(b.model? as usize),
+            _ => None,
+        }
+    }
 }
 
 impl Default for IdMapping {
     fn default() -> Self {
         Self {
-            mapping: vec![BlockType::None, BlockType::Stone(Stone)],
+            mapping: vec![
+                BlockType::None,
+                BlockType::Stone(Stone),
+                BlockType::Missing(Missing),
+            ],
+            models: Vec::new(),
         }
     }
 }
@@ -2,8 +2,17 @@ mod block_type;
 pub use block_type::*;
 mod id_mapping;
 pub use id_mapping::*;
+mod registry;
+pub use registry::*;
+mod atlas;
+pub use atlas::*;
+mod model;
+pub use model::*;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct BlockId(u64);
 
 impl BlockId {
@@ -0,0 +1,340 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{
+    blocks::{uv_axes, Mesh},
+    BlockFace, Pos,
+};
+
+use super::{tile_uv, BUILTIN_TILES};
+
+/// model coordinates are in 16ths of a block
+pub const MODEL_UNITS: u8 = 16;
+
+/// how a placed block is turned, around the center of the block
+///
+/// first flipped upside down, by half a turn around x, then turned around y
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockRotation {
+    /// quarter turns around +y, counter clockwise seen from above
+    pub turns: u8,
+    pub upside_down: bool,
+}
+
+impl BlockRotation {
+    pub fn new(turns: u8, upside_down: bool) -> BlockRotation {
+        BlockRotation {
+            turns: turns % 4,
+            upside_down,
+        }
+    }
+
+    /// 0 to 7, the suffix of the block name of a rotated block
+    pub fn to_index(self) -> u8 {
+        self.turns + if self.upside_down { 4 } else { 0 }
+    }
+
+    /// rotate a vector around the origin
+    fn apply_centered(self, [mut x, mut y, mut z]: [i64; 3]) -> [i64; 3] {
+        if self.upside_down {
+            (y, z) = (-y, -z);
+        }
+        for _ in 0..self.turns {
+            (x, z) = (z, -x);
+        }
+        [x, y, z]
+    }
+
+    /// rotate a point of a model
+    pub fn apply(self, p: [u8; 3]) -> [u8; 3] {
+        let half = (MODEL_UNITS / 2) as i64;
+        self.apply_centered(p.map(|v| v as i64 * 2 - half * 2))
+            .map(|v| ((v + half * 2) / 2) as u8)
+    }
+
+    pub fn rotate_face(self, face: BlockFace) -> BlockFace {
+        let p = Pos::from_array(self.apply_centered(face.to_pos().to_array()));
+        BlockFace::iter_all().find(|f| f.to_pos() == p).unwrap()
+    }
+
+    /// the face that `rotate_face` turns into `face`
+    pub fn unrotate_face(self, face: BlockFace) -> BlockFace {
+        BlockFace::iter_all()
+            .find(|f| self.rotate_face(*f) == face)
+            .unwrap()
+    }
+}
+
+/// the rotations a block definition registers a block for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotations {
+    #[default]
+    None,
+    /// the four turns around y
+    Horizontal,
+    /// the four turns, and each of them upside down
+    All,
+}
+
+impl Rotations {
+    pub fn iter(self) -> impl Iterator<Item = BlockRotation> {
+        let (turns, flips) = match self {
+            Rotations::None => (1, 1),
+            Rotations::Horizontal => (4, 1),
+            Rotations::All => (4, 2),
+        };
+        (0..flips).flat_map(move |f| (0..turns).map(move |t| BlockRotation::new(t, f == 1)))
+    }
+}
+
+/// a part of a block model in a block definition, in 16ths of a block
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelElement {
+    /// an axis aligned box, its faces use the textures of the definition
+    ///
+    /// a face on the side of the block is hidden by an opaque neighbour
+    Box { from: [u8; 3], to: [u8; 3] },
+    /// a quad with its corners counter clockwise seen from the front, with the texture
+    /// `texture` or else the `all` texture of the definition
+    ///
+    /// never hidden by a neighbour
+    Quad {
+        vertices: [[u8; 3]; 4],
+        #[serde(default)]
+        texture: Option<String>,
+        /// also draw the back
+        #[serde(default)]
+        double_sided: bool,
+    },
+}
+
+/// one quad of a `BlockModel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ModelQuad {
+    /// counter clockwise seen from the front, in 16ths of a block
+    pub vertices: [[u8; 3]; 4],
+    /// the uv of every vertex in 16ths of the texture
+    pub uv: [[u8; 2]; 4],
+    /// the index in `BlockRegistry::textures`
+    pub texture: u16,
+    /// the quad is hidden when the neighbour on this side is opaque
+    pub cull: Option<BlockFace>,
+}
+
+/// the shape of a `BlockVisibility::Special` block
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockModel {
+    pub quads: Vec<ModelQuad>,
+}
+
+impl BlockModel {
+    /// `texture(name, face)` gives the texture index of the `texture` of a quad, `(None, None)` for a quad without one,
+    /// or of the definition on that face of a box
+    pub fn from_elements<F>(elements: &[ModelElement], mut texture: F) -> Result<BlockModel, String>
+    where
+        F: FnMut(Option<&str>, Option<BlockFace>) -> Option<u16>,
+    {
+        let mut quads = Vec::new();
+        for element in elements {
+            match element {
+                ModelElement::Box { from, to } => {
+                    if (0..3).any(|i| from[i] >= to[i] || to[i] > MODEL_UNITS) {
+                        return Err(format!("{from:?} to {to:?} is not a box in the block"));
+                    }
+                    for face in BlockFace::iter_all() {
+                        let texture = texture(None, Some(face))
+                            .ok_or_else(|| format!("no texture for {face:?}"))?;
+                        quads.push(box_face(*from, *to, face, texture));
+                    }
+                }
+                ModelElement::Quad {
+                    vertices,
+                    texture: name,
+                    double_sided,
+                } => {
+                    if vertices.iter().flatten().any(|v| *v > MODEL_UNITS) {
+                        return Err(format!("{vertices:?} is not in the block"));
+                    }
+                    let texture = texture(name.as_deref(), None)
+                        .ok_or_else(|| format!("no texture {name:?}"))?;
+                    let uv = [[0, 16], [16, 16], [16, 0], [0, 0]];
+                    quads.push(ModelQuad {
+                        vertices: *vertices,
+                        uv,
+                        texture,
+                        cull: None,
+                    });
+                    if *double_sided {
+                        let [a, b, c, d] = *vertices;
+                        quads.push(ModelQuad {
+                            vertices: [b, a, d, c],
+                            uv: [uv[1], uv[0], uv[3], uv[2]],
+                            texture,
+                            cull: None,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(BlockModel { quads })
+    }
+
+    /// add the model to a mesh
+    ///
+    /// `hidden` has the bit `1 << face.to_index()` set for every face of the block next to an opaque block
+    pub fn push_to_mesh(&self, mesh: &mut Mesh, pos: Pos, rotation: BlockRotation, hidden: u8) {
+        let base = pos.to_f32_truple();
+        for quad in self.quads.iter() {
+            if let Some(face) = quad.cull {
+                if hidden & (1 << rotation.rotate_face(face).to_index()) != 0 {
+                    continue;
+                }
+            }
+            let v = quad.vertices.map(|v| {
+                let [x, y, z] = rotation.apply(v).map(|v| v as f32 / MODEL_UNITS as f32);
+                (base.0 + x, base.1 + y, base.2 + z)
+            });
+            let normal = normal_of(v[0], v[1], v[2]);
+            let uv = quad.uv.map(|[u, v]| (u as f32 / 16., v as f32 / 16.));
+            mesh.push_quad(
+                (
+                    [
+                        (v[0], normal, uv[0]),
+                        (v[1], normal, uv[1]),
+                        (v[2], normal, uv[2]),
+                        (v[3], normal, uv[3]),
+                    ],
+                    [0, 1, 2, 0, 2, 3],
+                ),
+                tile_uv(BUILTIN_TILES + quad.texture),
+            );
+        }
+    }
+}
+
+fn normal_of(a: (f32, f32, f32), b: (f32, f32, f32), c: (f32, f32, f32)) -> (f32, f32, f32) {
+    let (u, v) = (
+        (b.0 - a.0, b.1 - a.1, b.2 - a.2),
+        (c.0 - a.0, c.1 - a.1, c.2 - a.2),
+    );
+    let n = (
+        u.1 * v.2 - u.2 * v.1,
+        u.2 * v.0 - u.0 * v.2,
+        u.0 * v.1 - u.1 * v.0,
+    );
+    let len = (n.0 * n.0 + n.1 * n.1 + n.2 * n.2).sqrt();
+    (n.0 / len, n.1 / len, n.2 / len)
+}
+
+/// one face of a box, counter clockwise from outside, with the uv of the same face of a full block
+fn box_face(from: [u8; 3], to: [u8; 3], face: BlockFace, texture: u16) -> ModelQuad {
+    let (axis_u, axis_v) = uv_axes(face);
+    let axis_n = 3 - axis_u - axis_v;
+    let positive = face.to_pos().to_array()[axis_n] > 0;
+    let corner = |du: bool, dv: bool| {
+        let mut p = [0; 3];
+        p[axis_n] = if positive { to[axis_n] } else { from[axis_n] };
+        p[axis_u] = if du { to[axis_u] } else { from[axis_u] };
+        p[axis_v] = if dv { to[axis_v] } else { from[axis_v] };
+        p
+    };
+    let mut vertices = [
+        corner(false, false),
+        corner(true, false),
+        corner(true, true),
+        corner(false, true),
+    ];
+    // the corners are counter clockwise around u cross v, which points along +n or -n
+    if ((axis_u + 1) % 3 == axis_v) != positive {
+        vertices.swap(1, 3);
+    }
+    let uv = vertices.map(|p| [p[axis_u], p[axis_v]]);
+    let on_side = if positive {
+        to[axis_n] == MODEL_UNITS
+    } else {
+        from[axis_n] == 0
+    };
+    ModelQuad {
+        vertices,
+        uv,
+        texture,
+        cull: on_side.then_some(face),
+    }
+}
+
+#[test]
+fn test_block_rotation() {
+    let r = BlockRotation::new(1, false);
+    assert_eq!(r.rotate_face(BlockFace::XP), BlockFace::ZN);
+    assert_eq!(r.apply([16, 0, 0]), [0, 0, 0]);
+    assert_eq!(r.apply([16, 4, 16]), [16, 4, 0]);
+    let flipped = BlockRotation::new(0, true);
+    assert_eq!(flipped.rotate_face(BlockFace::YN), BlockFace::YP);
+    assert_eq!(flipped.apply([0, 0, 0]), [0, 16, 16]);
+    for r in Rotations::All.iter() {
+        for face in BlockFace::iter_all() {
+            assert_eq!(r.unrotate_face(r.rotate_face(face)), face);
+        }
+    }
+    assert_eq!(Rotations::All.iter().count(), 8);
+    assert_eq!(
+        Rotations::None.iter().collect::<Vec<_>>(),
+        [BlockRotation::default()]
+    );
+}
+
+#[test]
+fn test_block_model() {
+    // a slab
+    let model = BlockModel::from_elements(
+        &[ModelElement::Box {
+            from: [0, 0, 0],
+            to: [16, 8, 16],
+        }],
+        |_, face| face.map(|f| f.to_index() as u16),
+    )
+    .unwrap();
+    assert_eq!(model.quads.len(), 6);
+    let top = model.quads[BlockFace::YP.to_index()];
+    assert_eq!(top.cull, None);
+    assert!(top.vertices.iter().all(|v| v[1] == 8));
+    assert_eq!(
+        model.quads[BlockFace::YN.to_index()].cull,
+        Some(BlockFace::YN)
+    );
+
+    let mut mesh = Mesh::default();
+    model.push_to_mesh(&mut mesh, Pos::default(), BlockRotation::default(), 0);
+    assert_eq!(mesh.vertices.len(), 24);
+    mesh.validate().unwrap();
+    // every face points out of the box
+    for (quad, face) in mesh.normal.chunks(4).zip(BlockFace::iter_all()) {
+        let n = face.to_pos().to_array().map(|v| v as f32);
+        assert_eq!(quad[0], n);
+    }
+
+    // the bottom is hidden by an opaque block below, upside down the top is
+    let mut mesh = Mesh::default();
+    let hidden = 1 << BlockFace::YN.to_index();
+    model.push_to_mesh(&mut mesh, Pos::default(), BlockRotation::default(), hidden);
+    assert_eq!(mesh.vertices.len(), 20);
+    let mut mesh = Mesh::default();
+    model.push_to_mesh(
+        &mut mesh,
+        Pos::default(),
+        BlockRotation::new(0, true),
+        hidden,
+    );
+    assert_eq!(mesh.vertices.len(), 24);
+    assert!(mesh.vertices.iter().all(|v| v[1] >= 0.5));
+
+    assert!(BlockModel::from_elements(
+        &[ModelElement::Box {
+            from: [0, 8, 0],
+            to: [16, 8, 16],
+        }],
+        |_, _| Some(0),
+    )
+    .is_err());
+}
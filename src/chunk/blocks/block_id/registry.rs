@@ -0,0 +1,539 @@
+use std::{borrow::Cow, collections::HashMap, fs, io, path::Path};
+
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{chunk::MAX_LIGHT, BlockFace, BlockVisibility};
+
+use super::{
+    tile_uv, BlockClient, BlockModel, BlockRotation, BlockType, IdMapping, Missing, ModelElement,
+    Rotations, Stone, BUILTIN_TILES,
+};
+
+/// the namespace of block names given without one
+pub const DEFAULT_NAMESPACE: &str = "phyvox";
+
+/// the blocks every registry has, with the names they are saved under
+const BUILTIN_BLOCKS: [(&str, BlockType); 3] = [
+    ("phyvox:none", BlockType::None),
+    ("phyvox:stone", BlockType::Stone(Stone)),
+    ("phyvox:missing", BlockType::Missing(Missing)),
+];
+
+/// `namespace:path`, a name without a namespace is in `DEFAULT_NAMESPACE`
+///
+/// `None` if either part is empty or there is more than one `:`
+pub fn qualified_block_name(name: &str) -> Option<Cow<'_, str>> {
+    match name.split_once(':') {
+        None if !name.is_empty() => Some(Cow::Owned(format!("{DEFAULT_NAMESPACE}:{name}"))),
+        Some((namespace, path))
+            if !namespace.is_empty() && !path.is_empty() && !path.contains(':') =>
+        {
+            Some(Cow::Borrowed(name))
+        }
+        _ => None,
+    }
+}
+
+/// a block defined in a `BlockRegistry`
+///
+/// carries what meshing needs, so going from a `BlockId` to it does not touch the registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RegisteredBlock {
+    /// the index of the definition in `BlockRegistry::definitions`
+    pub index: u16,
+    pub visibility: BlockVisibility,
+    /// the index in `BlockRegistry::textures` of the texture on every face, by `BlockFace::to_index`
+    pub textures: [u16; 6],
+    /// every rotation of a definition is its own block
+    pub rotation: BlockRotation,
+    /// the index in `IdMapping::models` of the model of a special block
+    pub model: Option<u16>,
+    /// the block light it gives off
+    pub emission: u8,
+}
+
+impl BlockClient for RegisteredBlock {
+    fn visibility(self) -> BlockVisibility {
+        self.visibility
+    }
+
+    /// the tile of the texture in the `BlockAtlas`
+    fn get_uv(self, face: BlockFace) -> ((f32, f32), (f32, f32)) {
+        tile_uv(BUILTIN_TILES + self.textures[self.rotation.unrotate_face(face).to_index()])
+    }
+
+    fn emission(self) -> u8 {
+        self.emission
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockPhysics {
+    /// if things collide with the block
+    pub solid: bool,
+    pub friction: f32,
+    pub restitution: f32,
+}
+
+impl Default for BlockPhysics {
+    fn default() -> Self {
+        Self {
+            solid: true,
+            friction: 0.6,
+            restitution: 0.0,
+        }
+    }
+}
+
+/// the texture names of a definition, a face uses the most specific one given
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaceTextures {
+    pub all: Option<String>,
+    /// every face but the top and the bottom
+    pub side: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub xp: Option<String>,
+    pub xn: Option<String>,
+    pub yp: Option<String>,
+    pub yn: Option<String>,
+    pub zp: Option<String>,
+    pub zn: Option<String>,
+}
+
+impl FaceTextures {
+    pub fn get(&self, face: BlockFace) -> Option<&str> {
+        let (exact, group) = match face {
+            BlockFace::XP => (&self.xp, &self.side),
+            BlockFace::XN => (&self.xn, &self.side),
+            BlockFace::YP => (&self.yp, &self.top),
+            BlockFace::YN => (&self.yn, &self.bottom),
+            BlockFace::ZP => (&self.zp, &self.side),
+            BlockFace::ZN => (&self.zn, &self.side),
+        };
+        exact
+            .as_ref()
+            .or(group.as_ref())
+            .or(self.all.as_ref())
+            .map(|s| s.as_str())
+    }
+}
+
+/// one block in a block definition file
+///
+/// the name is qualified with `qualified_block_name` when it is registered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockDefinition {
+    pub name: String,
+    pub visibility: BlockVisibility,
+    #[serde(default)]
+    pub textures: FaceTextures,
+    #[serde(default)]
+    pub physics: BlockPhysics,
+    /// the shape of a `BlockVisibility::Special` block, boxes use `textures`
+    #[serde(default)]
+    pub model: Option<Vec<ModelElement>>,
+    /// every rotation is registered as its own block, named `name#index` but for no rotation
+    #[serde(default)]
+    pub rotations: Rotations,
+    /// the block light the block gives off, up to `MAX_LIGHT`
+    #[serde(default)]
+    pub emission: u8,
+}
+
+#[derive(Debug, Deserialize)]
+struct DefinitionFile {
+    blocks: Vec<BlockDefinition>,
+}
+
+/// the blocks defined in data, next to the built in `BlockType::None` and `BlockType::Stone`
+///
+/// a block definition file is `{ "blocks": [BlockDefinition, ..] }`
+#[derive(Debug, Clone, PartialEq, Resource)]
+pub struct BlockRegistry {
+    definitions: Vec<BlockDefinition>,
+    /// the rotations of every definition, in the order of `definitions`
+    blocks: Vec<RegisteredBlock>,
+    /// the names of `blocks`
+    names: Vec<String>,
+    textures: Vec<String>,
+    models: Vec<BlockModel>,
+    by_name: HashMap<String, BlockType>,
+}
+
+/// only the built in blocks
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        BlockRegistry::from_definitions(Vec::new()).unwrap()
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// the index of a texture name, added to `textures` the first time
+fn texture_index(textures: &mut Vec<String>, index: &mut HashMap<String, u16>, name: &str) -> u16 {
+    *index.entry(name.to_owned()).or_insert_with(|| {
+        textures.push(name.to_owned());
+        textures.len() as u16 - 1
+    })
+}
+
+impl BlockRegistry {
+    pub fn from_definitions(mut definitions: Vec<BlockDefinition>) -> io::Result<BlockRegistry> {
+        let mut registry = BlockRegistry {
+            definitions: Vec::new(),
+            blocks: Vec::new(),
+            names: Vec::new(),
+            textures: Vec::new(),
+            models: Vec::new(),
+            by_name: HashMap::new(),
+        };
+        for (name, block) in BUILTIN_BLOCKS {
+            registry.by_name.insert(name.to_owned(), block);
+        }
+        let mut texture_indices = HashMap::new();
+        for (index, definition) in definitions.iter_mut().enumerate() {
+            definition.name = qualified_block_name(&definition.name)
+                .filter(|name| !name.contains('#'))
+                .ok_or_else(|| invalid_data(format!("{:?} is not a block name", definition.name)))?
+                .into_owned();
+            if registry.by_name.contains_key(&definition.name) {
+                return Err(invalid_data(format!(
+                    "the block {} is defined twice",
+                    definition.name
+                )));
+            }
+            if definition.emission > MAX_LIGHT {
+                return Err(invalid_data(format!(
+                    "the emission of the block {} is more than {MAX_LIGHT}",
+                    definition.name
+                )));
+            }
+            // a special block only needs the textures its model uses
+            let mut textures = [0; 6];
+            for face in BlockFace::iter_all() {
+                match definition.textures.get(face) {
+                    Some(name) => {
+                        textures[face.to_index()] =
+                            texture_index(&mut registry.textures, &mut texture_indices, name)
+                    }
+                    None if matches!(
+                        definition.visibility,
+                        BlockVisibility::Opaque | BlockVisibility::Transparent
+                    ) =>
+                    {
+                        return Err(invalid_data(format!(
+                            "the block {} has no texture for {:?}",
+                            definition.name, face
+                        )))
+                    }
+                    None => {}
+                }
+            }
+            let model = match (&definition.model, definition.visibility) {
+                (Some(elements), BlockVisibility::Special) => {
+                    let model = BlockModel::from_elements(elements, |name, face| {
+                        let name = match (name, face) {
+                            (Some(name), _) => name,
+                            (None, Some(face)) => definition.textures.get(face)?,
+                            (None, None) => definition.textures.all.as_deref()?,
+                        };
+                        Some(texture_index(
+                            &mut registry.textures,
+                            &mut texture_indices,
+                            name,
+                        ))
+                    })
+                    .map_err(|e| {
+                        invalid_data(format!("the model of the block {}: {e}", definition.name))
+                    })?;
+                    registry.models.push(model);
+                    Some(registry.models.len() as u16 - 1)
+                }
+                (None, BlockVisibility::Special) => {
+                    return Err(invalid_data(format!(
+                        "the special block {} has no model",
+                        definition.name
+                    )))
+                }
+                (Some(_), _) => {
+                    return Err(invalid_data(format!(
+                        "the block {} has a model but is not special",
+                        definition.name
+                    )))
+                }
+                (None, _) => None,
+            };
+            let index = u16::try_from(index)
+                .map_err(|_| invalid_data("too many block definitions".to_owned()))?;
+            for rotation in definition.rotations.iter() {
+                let block = RegisteredBlock {
+                    index,
+                    visibility: definition.visibility,
+                    textures,
+                    rotation,
+                    model,
+                    emission: definition.emission,
+                };
+                let name = match rotation.to_index() {
+                    0 => definition.name.clone(),
+                    i => format!("{}#{i}", definition.name),
+                };
+                registry.blocks.push(block);
+                registry.names.push(name.clone());
+                registry.by_name.insert(name, BlockType::Registered(block));
+            }
+        }
+        registry.definitions = definitions;
+        Ok(registry)
+    }
+
+    pub fn from_json(json: &str) -> io::Result<BlockRegistry> {
+        let file: DefinitionFile = serde_json::from_str(json)?;
+        BlockRegistry::from_definitions(file.blocks)
+    }
+
+    /// read a block definition file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<BlockRegistry> {
+        BlockRegistry::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn definitions(&self) -> &[BlockDefinition] {
+        &self.definitions
+    }
+
+    /// every texture name used by a definition, indexed by `RegisteredBlock::textures`
+    pub fn textures(&self) -> &[String] {
+        &self.textures
+    }
+
+    /// the built in or registered block with this name, see `qualified_block_name`
+    pub fn get(&self, name: &str) -> Option<BlockType> {
+        self.by_name
+            .get(qualified_block_name(name)?.as_ref())
+            .copied()
+    }
+
+    pub fn name_of(&self, block: BlockType) -> Option<&str> {
+        match block {
+            BlockType::Registered(b) => self
+                .blocks
+                .iter()
+                .position(|v| *v == b)
+                .map(|i| self.names[i].as_str()),
+            _ => BUILTIN_BLOCKS
+                .iter()
+                .find(|(_, v)| *v == block)
+                .map(|(name, _)| *name),
+        }
+    }
+
+    /// the same block turned by `rotation`, if its definition has that rotation
+    pub fn rotated(&self, block: BlockType, rotation: BlockRotation) -> Option<BlockType> {
+        match block {
+            BlockType::Registered(b) => self
+                .blocks
+                .iter()
+                .find(|v| v.index == b.index && v.rotation == rotation)
+                .map(|v| BlockType::Registered(*v)),
+            _ => (rotation == BlockRotation::default()).then_some(block),
+        }
+    }
+
+    pub fn physics(&self, block: BlockType) -> BlockPhysics {
+        match block {
+            BlockType::None => BlockPhysics {
+                solid: false,
+                ..Default::default()
+            },
+            BlockType::Stone(_) | BlockType::Missing(_) => BlockPhysics::default(),
+            BlockType::Registered(b) => self.definitions[b.index as usize].physics,
+        }
+    }
+
+    /// the built in blocks at the ids of `IdMapping::default`, then every rotation of every registered block
+    pub fn id_mapping(&self) -> IdMapping {
+        let mut id_mapping = IdMapping::default();
+        id_mapping
+            .mapping
+            .extend(self.blocks.iter().map(|b| BlockType::Registered(*b)));
+        id_mapping.models = self.models.clone();
+        id_mapping
+    }
+}
+
+#[test]
+fn test_block_registry() {
+    use crate::chunk::blocks::BlockId;
+
+    let registry = BlockRegistry::from_json(
+        r#"{
+            "blocks": [
+                {
+                    "name": "grass",
+                    "visibility": "Opaque",
+                    "textures": { "all": "dirt", "top": "grass_top", "side": "grass_side" },
+                    "physics": { "friction": 0.8 }
+                },
+                { "name": "glass", "visibility": "Transparent", "textures": { "all": "glass" } },
+                { "name": "air2", "visibility": "Empty", "physics": { "solid": false } }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        registry.textures(),
+        ["grass_side", "grass_top", "dirt", "glass"]
+    );
+
+    let id_mapping = registry.id_mapping();
+    let grass = BlockId::from(3_u64).get_block_type(&id_mapping).unwrap();
+    assert_eq!(registry.get("grass"), Some(grass));
+    assert_eq!(registry.get("phyvox:grass"), Some(grass));
+    assert_eq!(registry.name_of(grass), Some("phyvox:grass"));
+    assert_eq!(grass.visibility(), BlockVisibility::Opaque);
+    match grass {
+        BlockType::Registered(b) => {
+            assert_eq!(b.textures[BlockFace::YP.to_index()], 1);
+            assert_eq!(b.textures[BlockFace::YN.to_index()], 2);
+            assert_eq!(b.textures[BlockFace::ZN.to_index()], 0);
+        }
+        _ => panic!(),
+    }
+    assert_eq!(registry.physics(grass).friction, 0.8);
+    assert!(registry.physics(grass).solid);
+
+    let glass = BlockId::from(4_u64).get_block_type(&id_mapping).unwrap();
+    assert_eq!(glass.visibility(), BlockVisibility::Transparent);
+    let air2 = BlockId::from(5_u64).get_block_type(&id_mapping).unwrap();
+    assert!(!registry.physics(air2).solid);
+
+    assert_eq!(registry.get("stone"), Some(BlockType::Stone(Stone)));
+    assert_eq!(registry.name_of(BlockType::None), Some("phyvox:none"));
+    assert_eq!(&id_mapping.mapping[..3], &IdMapping::default().mapping[..]);
+    assert_eq!(id_mapping.id_of(grass), Some(3_u64.into()));
+    assert_eq!(registry.get("other:grass"), None);
+    for name in ["", ":a", "a:", "a:b:c"] {
+        assert_eq!(qualified_block_name(name), None);
+    }
+
+    let duplicate = r#"{ "blocks": [{ "name": "stone", "visibility": "Empty" }] }"#;
+    assert!(BlockRegistry::from_json(duplicate).is_err());
+    let untextured = r#"{ "blocks": [{ "name": "a", "visibility": "Opaque" }] }"#;
+    assert!(BlockRegistry::from_json(untextured).is_err());
+}
+
+#[test]
+fn test_block_definition_file() {
+    let registry = BlockRegistry::load("assets/blocks.json").unwrap();
+    assert!(registry.get("grass").is_some());
+    let rotations = registry
+        .definitions()
+        .iter()
+        .map(|d| d.rotations.iter().count())
+        .sum::<usize>();
+    assert_eq!(
+        registry.id_mapping().mapping.len(),
+        IdMapping::default().mapping.len() + rotations
+    );
+    let slab = registry.get("stone_slab").unwrap();
+    assert_eq!(slab.visibility(), BlockVisibility::Special);
+    assert!(registry.id_mapping().model_of(slab).is_some());
+}
+
+#[test]
+fn test_block_models_and_rotations() {
+    let registry = BlockRegistry::from_json(
+        r#"{
+            "blocks": [
+                {
+                    "name": "stairs",
+                    "visibility": "Special",
+                    "textures": { "all": "planks" },
+                    "rotations": "horizontal",
+                    "model": [
+                        { "box": { "from": [0, 0, 0], "to": [16, 8, 16] } },
+                        { "box": { "from": [0, 8, 8], "to": [16, 16, 16] } }
+                    ]
+                },
+                {
+                    "name": "fern",
+                    "visibility": "Special",
+                    "physics": { "solid": false },
+                    "model": [
+                        { "quad": { "vertices": [[0, 0, 0], [16, 0, 16], [16, 16, 16], [0, 16, 0]], "texture": "fern", "double_sided": true } },
+                        { "quad": { "vertices": [[16, 0, 0], [0, 0, 16], [0, 16, 16], [16, 16, 0]], "texture": "fern", "double_sided": true } }
+                    ]
+                },
+                {
+                    "name": "sign",
+                    "visibility": "Special",
+                    "textures": { "all": "planks" },
+                    "model": [
+                        { "quad": { "vertices": [[0, 0, 8], [16, 0, 8], [16, 16, 8], [0, 16, 8]] } }
+                    ]
+                },
+                {
+                    "name": "furnace",
+                    "visibility": "Opaque",
+                    "textures": { "all": "furnace_side", "zp": "furnace_front" },
+                    "rotations": "all"
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        registry.textures(),
+        ["planks", "fern", "furnace_side", "furnace_front"]
+    );
+    let id_mapping = registry.id_mapping();
+    assert_eq!(id_mapping.mapping.len(), 3 + 4 + 1 + 1 + 8);
+    assert_eq!(id_mapping.models.len(), 3);
+
+    let stairs = registry.get("stairs").unwrap();
+    let turned = registry.get("stairs#1").unwrap();
+    assert_eq!(
+        registry.rotated(stairs, BlockRotation::new(1, false)),
+        Some(turned)
+    );
+    assert_eq!(registry.name_of(turned), Some("phyvox:stairs#1"));
+    assert_eq!(registry.rotated(stairs, BlockRotation::new(0, true)), None);
+    assert_eq!(id_mapping.model_of(stairs), id_mapping.model_of(turned));
+    assert_eq!(id_mapping.model_of(stairs).unwrap().quads.len(), 12);
+
+    let fern = registry.get("fern").unwrap();
+    assert_eq!(id_mapping.model_of(fern).unwrap().quads.len(), 4);
+    assert!(!registry.physics(fern).solid);
+
+    // a quad without a texture uses the `all` texture
+    let sign = id_mapping.model_of(registry.get("sign").unwrap()).unwrap();
+    assert_eq!(sign.quads.len(), 1);
+    assert_eq!(sign.quads[0].texture, 0);
+
+    // the front of a turned furnace faces x
+    let furnace = registry.get("furnace").unwrap();
+    let turned = registry
+        .rotated(furnace, BlockRotation::new(1, false))
+        .unwrap();
+    assert_eq!(furnace.get_uv(BlockFace::ZP), tile_uv(BUILTIN_TILES + 3));
+    assert_eq!(turned.get_uv(BlockFace::XP), tile_uv(BUILTIN_TILES + 3));
+    assert_eq!(turned.get_uv(BlockFace::ZP), tile_uv(BUILTIN_TILES + 2));
+
+    for invalid in [
+        r#"{ "name": "a", "visibility": "Special" }"#,
+        r#"{ "name": "a", "visibility": "Special", "model": [{ "quad": { "vertices": [[0, 0, 0], [16, 0, 0], [16, 16, 0], [0, 16, 0]] } }] }"#,
+        r#"{ "name": "a", "visibility": "Opaque", "textures": { "all": "a" }, "model": [] }"#,
+        r#"{ "name": "a", "visibility": "Special", "model": [{ "box": { "from": [0, 0, 0], "to": [16, 16, 16] } }] }"#,
+        r#"{ "name": "a#1", "visibility": "Empty" }"#,
+        r#"{ "name": "a", "visibility": "Empty", "emission": 16 }"#,
+    ] {
+        let json = format!(r#"{{ "blocks": [{invalid}] }}"#);
+        assert!(BlockRegistry::from_json(&json).is_err(), "{invalid}");
+    }
+}
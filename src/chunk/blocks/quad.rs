@@ -1,10 +1,11 @@
 //use ahash::AHashMap;
+use bevy::math::{Vec2, Vec3};
 use rustc_hash::FxHashMap;
 //use std::collections::HashMap;
 
-use crate::chunk::{blocks::BlockClient, BlockFace, Pos};
+use crate::chunk::{blocks::BlockClient, chunk::MAX_LIGHT, BlockFace, BlockVisibility, Pos};
 
-use super::block_id::BlockType;
+use super::block_id::{BlockType, IdMapping};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Quad {
@@ -13,27 +14,370 @@ pub struct Quad {
     pub face: BlockFace,
 }
 
+/// a quad covering `size` blocks, the size along the face normal is always 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MergedQuad {
+    pub quad: Quad,
+    pub size: Pos,
+}
+
+/// a block next to the block passed to `QuadGroup::update_block`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NeighbourBlock {
+    /// in the same chunk, its faces are in the QuadGroup
+    Inside(BlockType),
+    /// in another chunk, `BlockType::None` if that chunk is not loaded
+    Outside(BlockType),
+}
+
+impl NeighbourBlock {
+    pub fn block_type(self) -> BlockType {
+        match self {
+            NeighbourBlock::Inside(t) => t,
+            NeighbourBlock::Outside(t) => t,
+        }
+    }
+}
+
+/// how a QuadGroup is turned into a Mesh
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MeshMode {
+    /// one quad per visible face
+    #[default]
+    Naive,
+    /// merge coplanar adjacent faces of the same block type into rectangles,
+    /// the texture repeats once per block across them, see `Mesh::tile`
+    Greedy,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct QuadGroup {
     quad_group: [FxHashMap<Pos, BlockType>; 6], // x+,x-,y+,y-,z+,z-
+    /// the `BlockVisibility::Special` blocks, with the bit `1 << face.to_index()` set for every
+    /// face next to an opaque block
+    special: FxHashMap<Pos, (BlockType, u8)>,
 }
+/// the (vertice, normal, uv) of the corners of a quad, the uv is in blocks
+pub type QuadVertices = [((f32, f32, f32), (f32, f32, f32), (f32, f32)); 4];
+
 #[derive(Debug, Clone, Default)]
 pub struct Mesh {
     pub vertices: Vec<[f32; 3]>,
     pub normal: Vec<[f32; 3]>,
+    /// in blocks, the texture repeats once per block
     pub uv: Vec<[f32; 2]>,
+    /// the rectangle (u0, v0, u1, v1) of the texture in the atlas of every vertex,
+    /// the shader maps the fraction of the uv into it, see `tile_uv`
+    pub tile: Vec<[f32; 4]>,
+    /// the direction the u of the uv grows along, with the sign of the bitangent in w, see `FaceGeometry`
+    pub tangent: Vec<[f32; 4]>,
+    /// the brightness of every vertex from ambient occlusion, 1 if nothing is next to it
+    pub ao: Vec<f32>,
+    /// the (sky, block) light level of every vertex
+    pub light: Vec<[u8; 2]>,
     pub indices: Vec<u32>,
 }
 
+/// what darkens the faces of a mesh, looked up around the quads of a QuadGroup
+pub trait MeshShading {
+    /// if the block at `pos` darkens the corners of the faces next to it, see `vertex_ao`
+    fn occludes(&self, _pos: Pos) -> bool {
+        false
+    }
+
+    /// the (sky, block) light at `pos`, the faces in front of it and the models in it get this light
+    fn light(&self, _pos: Pos) -> (u8, u8) {
+        (MAX_LIGHT, 0)
+    }
+}
+
+/// no ambient occlusion and full sky light everywhere
+impl MeshShading for () {}
+
+/// ambient occlusion from the blocks the function returns true for, full sky light
+impl<F: Fn(Pos) -> bool> MeshShading for F {
+    fn occludes(&self, pos: Pos) -> bool {
+        self(pos)
+    }
+}
+
+/// the brightness of a vertex for every value of `vertex_ao`
+pub const AO_BRIGHTNESS: [f32; 4] = [0.4, 0.6, 0.8, 1.0];
+
+/// the ambient occlusion of a corner of a face from the blocks in front of the face touching the corner,
+/// 0 in a crease and 3 with nothing around
+pub fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - side1 as u8 - side2 as u8 - corner as u8
+    }
+}
+
+/// turn the two triangles of a quad so they share the diagonal between its brighter corners,
+/// else the occlusion is interpolated differently depending on the orientation of the face
+///
+/// `i` are the indices of `Quad::generate_mesh`, `ao` the values of its vertices
+fn orient_diagonal(i: [u32; 6], ao: [u8; 4]) -> [u32; 6] {
+    let first = [i[0], i[1], i[2]];
+    let lone = match (0..3).find(|k| !i[3..].contains(&first[*k])) {
+        Some(k) => k,
+        None => return i,
+    };
+    let other = match i[3..].iter().find(|v| !first.contains(v)) {
+        Some(v) => *v,
+        None => return i,
+    };
+    // the corners of the quad in order are u, s1, w, s2 and the shared diagonal is s1 s2
+    let (u, s1, s2) = (first[lone], first[(lone + 1) % 3], first[(lone + 2) % 3]);
+    let w = other;
+    let ao_of = |v: u32| ao[v as usize];
+    if ao_of(u) + ao_of(w) > ao_of(s1) + ao_of(s2) {
+        [u, s1, w, u, w, s2]
+    } else {
+        i
+    }
+}
+
 impl Mesh {
     pub fn new_with_capacity(v: usize, i: usize) -> Mesh {
         Mesh {
             vertices: Vec::with_capacity(v),
             normal: Vec::with_capacity(v),
             uv: Vec::with_capacity(v),
+            tile: Vec::with_capacity(v),
+            tangent: Vec::with_capacity(v),
+            ao: Vec::with_capacity(v),
+            light: Vec::with_capacity(v),
             indices: Vec::with_capacity(i),
         }
     }
+
+    /// `tile` is the rectangle of the texture in the atlas, as `BlockClient::get_uv` gives it
+    pub fn push_quad(&mut self, (p, i): (QuadVertices, [u32; 6]), tile: ((f32, f32), (f32, f32))) {
+        let indices_base = self.vertices.len() as u32;
+        for indices in i {
+            self.indices.push(indices + indices_base);
+        }
+        let tangent = quad_tangent(&p);
+        for (vertice, normal, uv) in p {
+            self.vertices.push([vertice.0, vertice.1, vertice.2]);
+            self.normal.push([normal.0, normal.1, normal.2]);
+            self.uv.push([uv.0, uv.1]);
+            self.tile.push([tile.0 .0, tile.0 .1, tile.1 .0, tile.1 .1]);
+            self.tangent.push(tangent);
+            self.ao.push(1.);
+            self.light.push([MAX_LIGHT, 0]);
+        }
+    }
+
+    /// `push_quad` with the ambient occlusion of every vertex, see `Quad::ambient_occlusion`
+    pub fn push_quad_with_ao(
+        &mut self,
+        (p, i): (QuadVertices, [u32; 6]),
+        tile: ((f32, f32), (f32, f32)),
+        ao: [u8; 4],
+    ) {
+        self.push_quad((p, orient_diagonal(i, ao)), tile);
+        let first = self.ao.len() - 4;
+        for (brightness, ao) in self.ao[first..].iter_mut().zip(ao) {
+            *brightness = AO_BRIGHTNESS[ao as usize];
+        }
+    }
+
+    /// `push_quad_with_ao` with the (sky, block) light of the quad
+    pub fn push_shaded_quad(
+        &mut self,
+        quad: (QuadVertices, [u32; 6]),
+        tile: ((f32, f32), (f32, f32)),
+        ao: [u8; 4],
+        (sky, block): (u8, u8),
+    ) {
+        self.push_quad_with_ao(quad, tile, ao);
+        let first = self.light.len() - 4;
+        self.light[first..].fill([sky, block]);
+    }
+
+    /// order the quads from the furthest to the closest to `eye`, see `sort_quads_back_to_front`
+    pub fn sort_back_to_front(&mut self, eye: [f32; 3]) {
+        sort_quads_back_to_front(&self.vertices, &mut self.indices, eye);
+    }
+
+    /// check that every vertex has all attributes, the normals and tangents are unit vectors
+    /// at right angles, and every triangle is counter clockwise seen from the side its normals point to
+    pub fn validate(&self) -> Result<(), String> {
+        let n = self.vertices.len();
+        let lengths = [
+            ("normal", self.normal.len()),
+            ("uv", self.uv.len()),
+            ("tile", self.tile.len()),
+            ("tangent", self.tangent.len()),
+            ("ao", self.ao.len()),
+            ("light", self.light.len()),
+        ];
+        for (name, len) in lengths {
+            if len != n {
+                return Err(format!("{len} {name} values for {n} vertices"));
+            }
+        }
+        if !self.indices.chunks_exact(3).remainder().is_empty() {
+            return Err(format!(
+                "{} indices are not whole triangles",
+                self.indices.len()
+            ));
+        }
+        if let Some(i) = self.indices.iter().find(|i| **i as usize >= n) {
+            return Err(format!("index {i} out of {n} vertices"));
+        }
+
+        const EPSILON: f32 = 1e-4;
+        for (i, (normal, tangent)) in self.normal.iter().zip(self.tangent.iter()).enumerate() {
+            let normal = Vec3::from(*normal);
+            let t = Vec3::new(tangent[0], tangent[1], tangent[2]);
+            if (normal.length() - 1.).abs() > EPSILON || (t.length() - 1.).abs() > EPSILON {
+                return Err(format!(
+                    "vertex {i} has a normal or tangent that is not a unit vector"
+                ));
+            }
+            if normal.dot(t).abs() > EPSILON {
+                return Err(format!(
+                    "vertex {i} has a tangent that is not at right angles to the normal"
+                ));
+            }
+            if tangent[3].abs() != 1. {
+                return Err(format!("vertex {i} has a bitangent sign of {}", tangent[3]));
+            }
+        }
+        for (t, triangle) in self.indices.chunks_exact(3).enumerate() {
+            let [a, b, c] = [0, 1, 2].map(|k| Vec3::from(self.vertices[triangle[k] as usize]));
+            let front = (b - a).cross(c - a);
+            if front.length() < EPSILON {
+                return Err(format!("triangle {t} has no area"));
+            }
+            for i in triangle {
+                if front.dot(Vec3::from(self.normal[*i as usize])) <= 0. {
+                    return Err(format!(
+                        "triangle {t} is clockwise seen from its normal at vertex {i}"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the tangent of a flat quad from how its uv changes along its edges, see `Mesh::tangent`
+fn quad_tangent(p: &QuadVertices) -> [f32; 4] {
+    let normal = Vec3::from(p[0].1);
+    let (e1, e2) = (
+        Vec3::from(p[1].0) - Vec3::from(p[0].0),
+        Vec3::from(p[2].0) - Vec3::from(p[0].0),
+    );
+    let (d1, d2) = (
+        Vec2::from(p[1].2) - Vec2::from(p[0].2),
+        Vec2::from(p[2].2) - Vec2::from(p[0].2),
+    );
+    let r = d1.perp_dot(d2);
+    if r == 0. {
+        // the uv does not change across the quad, any direction along it will do
+        let [x, y, z] = normal.any_orthonormal_vector().to_array();
+        return [x, y, z, 1.];
+    }
+    let tangent = ((e1 * d2.y - e2 * d1.y) / r)
+        .reject_from(normal)
+        .normalize();
+    let bitangent = (e2 * d1.x - e1 * d2.x) / r;
+    let w = if normal.cross(tangent).dot(bitangent) < 0. {
+        -1.
+    } else {
+        1.
+    };
+    [tangent.x, tangent.y, tangent.z, w]
+}
+
+/// order the quads of a mesh built by `Mesh::push_quad` from the furthest to the closest to `eye`,
+/// so alpha blending draws what is behind first
+///
+/// every quad is 6 indices into 4 vertices, `eye` is in the space of the vertices
+pub fn sort_quads_back_to_front(vertices: &[[f32; 3]], indices: &mut [u32], eye: [f32; 3]) {
+    let distance = |quad: &[u32]| {
+        let center = quad.iter().fold([0.; 3], |c, i| {
+            let v = vertices[*i as usize];
+            [c[0] + v[0], c[1] + v[1], c[2] + v[2]]
+        });
+        (0..3)
+            .map(|i| (center[i] / quad.len() as f32 - eye[i]).powi(2))
+            .sum::<f32>()
+    };
+    let mut quads = indices
+        .chunks_exact(6)
+        .map(|q| (distance(q), <[u32; 6]>::try_from(q).unwrap()))
+        .collect::<Vec<_>>();
+    quads.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (to, (_, q)) in indices.chunks_exact_mut(6).zip(quads) {
+        to.copy_from_slice(&q);
+    }
+}
+
+/// the axes (as indices into `Pos::to_array`) that the u and v of the uv follow on this face
+pub(crate) fn uv_axes(face: BlockFace) -> (usize, usize) {
+    match face {
+        BlockFace::XP => (2, 1),
+        BlockFace::XN => (1, 2),
+        BlockFace::YP => (0, 2),
+        BlockFace::YN => (2, 0),
+        BlockFace::ZP => (1, 0),
+        BlockFace::ZN => (0, 1),
+    }
+}
+
+/// how the face of a block is laid out, derived from the face and its `uv_axes`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaceGeometry {
+    /// the corner at the start of the uv, relative to the block
+    pub origin: Pos,
+    /// the unit vectors the u and the v of the uv grow along
+    pub u: Pos,
+    pub v: Pos,
+    pub normal: (f32, f32, f32),
+    /// `u` with w = 1 if `v` is normal x tangent and -1 if it is the opposite,
+    /// the same convention as bevy's `Mesh::generate_tangents`
+    pub tangent: [f32; 4],
+    /// two triangles over the `corners`, counter clockwise seen from the front
+    pub indices: [u32; 6],
+}
+
+impl FaceGeometry {
+    pub fn of(face: BlockFace) -> FaceGeometry {
+        let normal = face.to_pos();
+        let (axis_u, axis_v) = uv_axes(face);
+        let unit = |axis: usize| {
+            let mut a = [0; 3];
+            a[axis] = 1;
+            Pos::from_array(a)
+        };
+        let (u, v) = (unit(axis_u), unit(axis_v));
+        // u x v is the normal or points the other way, then the corners go around clockwise
+        let to_vec = |p: Pos| Vec3::from(p.to_f32_truple());
+        let w = to_vec(u).cross(to_vec(v)).dot(to_vec(normal));
+        FaceGeometry {
+            origin: Pos::from_array(normal.to_array().map(|n| n.max(0))),
+            u,
+            v,
+            normal: normal.to_f32_truple(),
+            tangent: [u.x() as f32, u.y() as f32, u.z() as f32, w],
+            indices: if w > 0. {
+                [0, 1, 2, 1, 3, 2]
+            } else {
+                [0, 2, 1, 1, 2, 3]
+            },
+        }
+    }
+
+    /// the corners relative to the block at uv (0, 0), (1, 0), (0, 1) and (1, 1)
+    pub fn corners(&self) -> [Pos; 4] {
+        [0_i64, 1, 2, 3].map(|k| self.origin + (k & 1) * self.u + (k >> 1) * self.v)
+    }
 }
 
 impl Quad {
@@ -45,168 +389,99 @@ impl Quad {
         }
     }
 
-    /// ([(vertice, normal, uv); 4], [indices; 6])
-    pub fn generate_mesh(
-        &self,
-    ) -> (
-        [((f32, f32, f32), (f32, f32, f32), (f32, f32)); 4],
-        [u32; 6],
-    ) {
-        match self.face {
-            BlockFace::XP => {
-                let v0 = (self.pos + Pos::from_xyz(1, 0, 0)).to_f32_truple();
-                let v1 = (self.pos + Pos::from_xyz(1, 1, 0)).to_f32_truple();
-                let v2 = (self.pos + Pos::from_xyz(1, 0, 1)).to_f32_truple();
-                let v3 = (self.pos + Pos::from_xyz(1, 1, 1)).to_f32_truple();
-
-                let normal = (1., 0., 0.);
-
-                let uv = self.block_type.get_uv(self.face);
-                let uv0 = uv.0;
-                let uv1 = (uv.0 .0, uv.1 .1);
-                let uv2 = (uv.1 .0, uv.0 .1);
-                let uv3 = uv.1;
-
-                let indices: [u32; 6] = [0, 1, 2, 1, 3, 2];
-                (
-                    [
-                        (v0, normal, uv0),
-                        (v1, normal, uv1),
-                        (v2, normal, uv2),
-                        (v3, normal, uv3),
-                    ],
-                    indices,
-                )
-            }
-            BlockFace::XN => {
-                let v0 = (self.pos + Pos::from_xyz(0, 0, 0)).to_f32_truple();
-                let v1 = (self.pos + Pos::from_xyz(0, 1, 0)).to_f32_truple();
-                let v2 = (self.pos + Pos::from_xyz(0, 0, 1)).to_f32_truple();
-                let v3 = (self.pos + Pos::from_xyz(0, 1, 1)).to_f32_truple();
-
-                let normal = (-1., 0., 0.);
-
-                let uv = self.block_type.get_uv(self.face);
-                let uv0 = uv.0;
-                let uv1 = (uv.0 .0, uv.1 .1);
-                let uv2: (f32, f32) = (uv.1 .0, uv.0 .1);
-                let uv3 = uv.1;
-
-                let indices: [u32; 6] = [0, 2, 1, 1, 2, 3];
-                (
-                    [
-                        (v0, normal, uv0),
-                        (v1, normal, uv2),
-                        (v2, normal, uv1),
-                        (v3, normal, uv3),
-                    ],
-                    indices,
-                )
-            }
-            BlockFace::YP => {
-                let v0 = (self.pos + Pos::from_xyz(0, 1, 0)).to_f32_truple();
-                let v1 = (self.pos + Pos::from_xyz(1, 1, 0)).to_f32_truple();
-                let v2 = (self.pos + Pos::from_xyz(0, 1, 1)).to_f32_truple();
-                let v3 = (self.pos + Pos::from_xyz(1, 1, 1)).to_f32_truple();
-
-                let normal = (0., 1., 0.);
-
-                let uv = self.block_type.get_uv(self.face);
-                let uv0 = uv.0;
-                let uv1 = (uv.0 .0, uv.1 .1);
-                let uv2 = (uv.1 .0, uv.0 .1);
-                let uv3 = uv.1;
-
-                let indices: [u32; 6] = [0, 2, 1, 1, 2, 3];
-                (
-                    [
-                        (v0, normal, uv0),
-                        (v1, normal, uv2),
-                        (v2, normal, uv1),
-                        (v3, normal, uv3),
-                    ],
-                    indices,
-                )
-            }
-            BlockFace::YN => {
-                let v0 = (self.pos + Pos::from_xyz(0, 0, 0)).to_f32_truple();
-                let v1 = (self.pos + Pos::from_xyz(1, 0, 0)).to_f32_truple();
-                let v2 = (self.pos + Pos::from_xyz(0, 0, 1)).to_f32_truple();
-                let v3 = (self.pos + Pos::from_xyz(1, 0, 1)).to_f32_truple();
-
-                let normal = (0., -1., 0.);
-
-                let uv = self.block_type.get_uv(self.face);
-                let uv0 = uv.0;
-                let uv1 = (uv.0 .0, uv.1 .1);
-                let uv2 = (uv.1 .0, uv.0 .1);
-                let uv3 = uv.1;
-
-                let indices: [u32; 6] = [0, 1, 2, 1, 3, 2];
-                (
-                    [
-                        (v0, normal, uv0),
-                        (v1, normal, uv1),
-                        (v2, normal, uv2),
-                        (v3, normal, uv3),
-                    ],
-                    indices,
-                )
-            }
-            BlockFace::ZP => {
-                let v0 = (self.pos + Pos::from_xyz(0, 0, 1)).to_f32_truple();
-                let v1 = (self.pos + Pos::from_xyz(1, 0, 1)).to_f32_truple();
-                let v2 = (self.pos + Pos::from_xyz(0, 1, 1)).to_f32_truple();
-                let v3 = (self.pos + Pos::from_xyz(1, 1, 1)).to_f32_truple();
-
-                let normal = (0., 0., 1.);
-
-                let uv = self.block_type.get_uv(self.face);
-                let uv0 = uv.0;
-                let uv1 = (uv.0 .0, uv.1 .1);
-                let uv2 = (uv.1 .0, uv.0 .1);
-                let uv3 = uv.1;
-
-                let indices: [u32; 6] = [0, 1, 2, 1, 3, 2];
-                (
-                    [
-                        (v0, normal, uv0),
-                        (v1, normal, uv1),
-                        (v2, normal, uv2),
-                        (v3, normal, uv3),
-                    ],
-                    indices,
-                )
-            }
-            BlockFace::ZN => {
-                let v0 = (self.pos + Pos::from_xyz(0, 0, 0)).to_f32_truple();
-                let v1 = (self.pos + Pos::from_xyz(1, 0, 0)).to_f32_truple();
-                let v2 = (self.pos + Pos::from_xyz(0, 1, 0)).to_f32_truple();
-                let v3 = (self.pos + Pos::from_xyz(1, 1, 0)).to_f32_truple();
-
-                let normal = (0., 0., 1.);
-
-                let uv = self.block_type.get_uv(self.face);
-                let uv0 = uv.0;
-                let uv1 = (uv.0 .0, uv.1 .1);
-                let uv2 = (uv.1 .0, uv.0 .1);
-                let uv3 = uv.1;
-
-                let indices: [u32; 6] = [0, 2, 1, 1, 2, 3];
-                (
-                    [
-                        (v0, normal, uv0),
-                        (v1, normal, uv2),
-                        (v2, normal, uv1),
-                        (v3, normal, uv3),
-                    ],
-                    indices,
-                )
+    /// transparent quads go into their own mesh, see `QuadGroup::generate_transparent_mesh`
+    pub fn is_transparent(&self) -> bool {
+        self.block_type.visibility() == BlockVisibility::Transparent
+    }
+
+    /// the rectangle of the texture of the face in the atlas
+    pub fn tile(&self) -> ((f32, f32), (f32, f32)) {
+        self.block_type.get_uv(self.face)
+    }
+
+    /// the `vertex_ao` of the vertices of `generate_mesh`
+    ///
+    /// `occludes` tells if the block at a position darkens the corners next to it
+    pub fn ambient_occlusion<F: Fn(Pos) -> bool>(&self, occludes: F) -> [u8; 4] {
+        MergedQuad::new(*self, Pos::from_xyz(1, 1, 1)).ambient_occlusion(occludes)
+    }
+
+    /// ([(vertice, normal, uv); 4], [indices; 6]), laid out by the `FaceGeometry` of the face
+    pub fn generate_mesh(&self) -> (QuadVertices, [u32; 6]) {
+        let geometry = FaceGeometry::of(self.face);
+        let uv = [(0., 0.), (1., 0.), (0., 1.), (1., 1.)];
+        let corners = geometry.corners();
+        let vertices = [0, 1, 2, 3].map(|k| {
+            (
+                (self.pos + corners[k]).to_f32_truple(),
+                geometry.normal,
+                uv[k],
+            )
+        });
+        (vertices, geometry.indices)
+    }
+}
+
+impl MergedQuad {
+    pub fn new(quad: Quad, size: Pos) -> MergedQuad {
+        MergedQuad { quad, size }
+    }
+
+    /// the `vertex_ao` of the vertices of `generate_mesh`, from the blocks at the corners of the rectangle
+    ///
+    /// only right for the whole quad if every block it covers has the same value at all four corners
+    pub fn ambient_occlusion<F: Fn(Pos) -> bool>(&self, occludes: F) -> [u8; 4] {
+        let (axis_u, axis_v) = uv_axes(self.quad.face);
+        let base = self.quad.pos.to_array();
+        let size = self.size.to_array();
+        let layer = self.quad.pos + self.quad.face;
+        let (p, _) = self.generate_mesh();
+        p.map(|(v, _, _)| {
+            let v = [v.0, v.1, v.2];
+            // the block at this corner of the rectangle and the direction away from the rectangle
+            let mut cell = [0; 3];
+            let mut du = [0; 3];
+            let mut dv = [0; 3];
+            for (axis, d) in [(axis_u, &mut du), (axis_v, &mut dv)] {
+                if v[axis] > base[axis] as f32 {
+                    cell[axis] = size[axis] - 1;
+                    d[axis] = 1;
+                } else {
+                    d[axis] = -1;
+                }
             }
+            let cell = layer + Pos::from_array(cell);
+            let (du, dv) = (Pos::from_array(du), Pos::from_array(dv));
+            vertex_ao(
+                occludes(cell + du),
+                occludes(cell + dv),
+                occludes(cell + du + dv),
+            )
+        })
+    }
+
+    /// same layout as `Quad::generate_mesh`, the uv goes up to the size of the quad
+    pub fn generate_mesh(&self) -> (QuadVertices, [u32; 6]) {
+        let (mut p, i) = self.quad.generate_mesh();
+        let base = self.quad.pos.to_f32_truple();
+        let size = self.size.to_array().map(|s| s as f32);
+        let (axis_u, axis_v) = uv_axes(self.quad.face);
+        for (vertice, _, uv) in p.iter_mut() {
+            *vertice = (
+                base.0 + (vertice.0 - base.0) * size[0],
+                base.1 + (vertice.1 - base.1) * size[1],
+                base.2 + (vertice.2 - base.2) * size[2],
+            );
+            *uv = (uv.0 * size[axis_u], uv.1 * size[axis_v]);
         }
+        (p, i)
     }
 }
 
+/// the quads of one layer by their u and v, with the block type,
+/// the ambient occlusion if it is the same at every corner and the light
+type LayerCells = FxHashMap<(i64, i64), (BlockType, Option<u8>, (u8, u8))>;
+
 impl QuadGroup {
     /// insert a quad into quad group
     ///
@@ -222,6 +497,13 @@ impl QuadGroup {
         }
     }
 
+    /// remove the quad on this face of the block at `pos`
+    ///
+    /// returns the removed value
+    pub fn remove_quad(&mut self, pos: Pos, face: BlockFace) -> Option<BlockType> {
+        self.quad_group[face.to_index()].remove(&pos)
+    }
+
     /// returns if the group contains this value
     pub fn contains(&mut self, quad: Quad) -> bool {
         match quad.face {
@@ -265,23 +547,454 @@ impl QuadGroup {
             )
     }
 
+    /// if there are no quads, special blocks are not counted
+    pub fn is_empty(&self) -> bool {
+        self.quad_group.iter().all(|q| q.is_empty())
+    }
+
+    /// add a special block, `hidden` has a bit set for every face next to an opaque block
+    pub fn insert_special(&mut self, pos: Pos, block_type: BlockType, hidden: u8) {
+        self.special.insert(pos, (block_type, hidden));
+    }
+
+    pub fn special_blocks(&self) -> impl Iterator<Item = (Pos, BlockType, u8)> + '_ {
+        self.special.iter().map(|(p, (t, h))| (*p, *t, *h))
+    }
+
+    /// add the models of the special blocks to a mesh, leaving out the quads hidden by opaque neighbours
+    ///
+    /// a model gets the light of its own block
+    pub fn push_models<S: MeshShading>(&self, id_mapping: &IdMapping, shading: S, mesh: &mut Mesh) {
+        for (pos, block_type, hidden) in self.special_blocks() {
+            if let (BlockType::Registered(b), Some(model)) =
+                (block_type, id_mapping.model_of(block_type))
+            {
+                let first = mesh.light.len();
+                model.push_to_mesh(mesh, pos, b.rotation, hidden);
+                let (sky, block) = shading.light(pos);
+                mesh.light[first..].fill([sky, block]);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.quad_group.iter().map(|q| q.len()).sum()
+    }
+
+    /// the mesh of the opaque quads
     pub fn generate_mesh(&self) -> Mesh {
-        let mut mesh = Mesh::new_with_capacity(8192, 8192);
-        let mut indices_base = 0;
-        for i in self.iter() {
-            let (p, i) = i.generate_mesh();
-            for indices in i {
-                mesh.indices.push(indices + indices_base);
+        self.generate_shaded_mesh(MeshMode::Naive, ())
+    }
+
+    /// the mesh of the transparent quads, one quad per face so they can be sorted
+    pub fn generate_transparent_mesh(&self) -> Mesh {
+        self.generate_shaded_transparent_mesh(())
+    }
+
+    /// `generate_transparent_mesh` with the light of `shading`, without ambient occlusion
+    pub fn generate_shaded_transparent_mesh<S: MeshShading>(&self, shading: S) -> Mesh {
+        let mut mesh = Mesh::default();
+        for i in self.iter().filter(|q| q.is_transparent()) {
+            mesh.push_shaded_quad(
+                i.generate_mesh(),
+                i.tile(),
+                [3; 4],
+                shading.light(i.pos + i.face),
+            );
+        }
+        mesh
+    }
+
+    pub fn generate_mesh_with_mode(&self, mode: MeshMode) -> Mesh {
+        self.generate_shaded_mesh(mode, ())
+    }
+
+    /// the mesh of the opaque quads with the ambient occlusion and the light of `shading` at every vertex
+    ///
+    /// a face gets the light of the block in front of it
+    pub fn generate_shaded_mesh<S: MeshShading>(&self, mode: MeshMode, shading: S) -> Mesh {
+        if self.is_empty() {
+            return Mesh::default();
+        }
+        let occludes = |p: Pos| shading.occludes(p);
+        let push = |mesh: &mut Mesh, quad: (QuadVertices, [u32; 6]), ao, q: &Quad| {
+            mesh.push_shaded_quad(quad, q.tile(), ao, shading.light(q.pos + q.face))
+        };
+        match mode {
+            MeshMode::Naive => {
+                let mut mesh = Mesh::new_with_capacity(8192, 8192);
+                for i in self.iter().filter(|q| !q.is_transparent()) {
+                    push(
+                        &mut mesh,
+                        i.generate_mesh(),
+                        i.ambient_occlusion(occludes),
+                        &i,
+                    );
+                }
+                mesh
             }
-            for (vertice, normal, uv) in p {
-                mesh.vertices.push([vertice.0, vertice.1, vertice.2]);
-                mesh.normal.push([normal.0, normal.1, normal.2]);
-                mesh.uv.push([uv.0, uv.1]);
-                indices_base += 1;
+            MeshMode::Greedy => {
+                let mut mesh = Mesh::new_with_capacity(1024, 1024);
+                for i in self.generate_merged_quads_shaded(&shading) {
+                    push(
+                        &mut mesh,
+                        i.generate_mesh(),
+                        i.ambient_occlusion(occludes),
+                        &i.quad,
+                    );
+                }
+                mesh
             }
         }
-        mesh
     }
 
-    // pub fn update_block()
+    /// merge the opaque quads of every face and layer into rectangles
+    ///
+    /// grows each rectangle along u first, then along v
+    pub fn generate_merged_quads(&self) -> Vec<MergedQuad> {
+        self.generate_merged_quads_shaded(&())
+    }
+
+    /// `generate_merged_quads` that only merges quads with the same light and
+    /// the same ambient occlusion at every corner, so it can be interpolated across the rectangle
+    pub fn generate_merged_quads_shaded<S: MeshShading>(&self, shading: &S) -> Vec<MergedQuad> {
+        let occludes = |p: Pos| shading.occludes(p);
+        let mut out = Vec::new();
+        for (face, quads) in BlockFace::iter_all().zip(self.quad_group.iter()) {
+            let (axis_u, axis_v) = uv_axes(face);
+            let axis_n = 3 - axis_u - axis_v;
+
+            let mut layers: FxHashMap<i64, LayerCells> = FxHashMap::default();
+            for (pos, block_type) in quads {
+                if block_type.visibility() == BlockVisibility::Transparent {
+                    continue;
+                }
+                let [a, b, c, d] = Quad::new(*pos, *block_type, face).ambient_occlusion(occludes);
+                let ao = (a == b && b == c && c == d).then_some(a);
+                let light = shading.light(*pos + face);
+                let p = pos.to_array();
+                layers
+                    .entry(p[axis_n])
+                    .or_default()
+                    .insert((p[axis_u], p[axis_v]), (*block_type, ao, light));
+            }
+
+            for (layer, mut cells) in layers {
+                let mut keys = cells.keys().copied().collect::<Vec<_>>();
+                keys.sort_unstable_by_key(|&(u, v)| (v, u));
+                for (u, v) in keys {
+                    let cell = match cells.get(&(u, v)) {
+                        Some(t) => *t,
+                        None => continue,
+                    };
+                    let (block_type, ao, _) = cell;
+
+                    let mut w = 1;
+                    let mut h = 1;
+                    if ao.is_some() {
+                        while cells.get(&(u + w, v)) == Some(&cell) {
+                            w += 1;
+                        }
+                        while (0..w).all(|du| cells.get(&(u + du, v + h)) == Some(&cell)) {
+                            h += 1;
+                        }
+                    }
+                    for dv in 0..h {
+                        for du in 0..w {
+                            cells.remove(&(u + du, v + dv));
+                        }
+                    }
+
+                    let mut pos = [0; 3];
+                    pos[axis_n] = layer;
+                    pos[axis_u] = u;
+                    pos[axis_v] = v;
+                    let mut size = [1; 3];
+                    size[axis_u] = w;
+                    size[axis_v] = h;
+                    out.push(MergedQuad::new(
+                        Quad::new(Pos::from_array(pos), block_type, face),
+                        Pos::from_array(size),
+                    ));
+                }
+            }
+        }
+        out
+    }
+
+    pub fn generate_mesh_greedy(&self) -> Mesh {
+        self.generate_shaded_mesh(MeshMode::Greedy, ())
+    }
+
+    /// update the faces of one changed block and the faces its six neighbours show towards it
+    ///
+    /// `neighbours` is indexed by `BlockFace::to_index`
+    pub fn update_block(
+        &mut self,
+        pos: Pos,
+        old: BlockType,
+        new: BlockType,
+        neighbours: [NeighbourBlock; 6],
+    ) {
+        if old == new {
+            return;
+        }
+        self.special.remove(&pos);
+        if new.visibility() == BlockVisibility::Special {
+            let hidden = BlockFace::iter_all()
+                .filter(|f| {
+                    neighbours[f.to_index()].block_type().visibility() == BlockVisibility::Opaque
+                })
+                .fold(0, |h, f| h | 1 << f.to_index());
+            self.special.insert(pos, (new, hidden));
+        }
+        let opaque = new.visibility() == BlockVisibility::Opaque;
+        for face in BlockFace::iter_all() {
+            let neighbour = neighbours[face.to_index()];
+
+            if let Some((_, hidden)) = self.special.get_mut(&(pos + face)) {
+                let bit = 1 << face.opposite().to_index();
+                if opaque {
+                    *hidden |= bit;
+                } else {
+                    *hidden &= !bit;
+                }
+            }
+
+            if new.shows_face_to(neighbour.block_type()) {
+                self.insert_quad(Quad::new(pos, new, face));
+            } else {
+                self.remove_quad(pos, face);
+            }
+
+            if let NeighbourBlock::Inside(t) = neighbour {
+                if t.shows_face_to(new) {
+                    self.insert_quad(Quad::new(pos + face, t, face.opposite()));
+                } else {
+                    self.remove_quad(pos + face, face.opposite());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_greedy_floor() {
+    use super::Stone;
+
+    let mut q = QuadGroup::default();
+    for x in 0..16 {
+        for z in 0..16 {
+            q.insert_quad(Quad::new(
+                Pos::from_xyz(x, 0, z),
+                BlockType::Stone(Stone),
+                BlockFace::YP,
+            ));
+        }
+    }
+    let naive = q.generate_mesh_with_mode(MeshMode::Naive);
+    let greedy = q.generate_mesh_with_mode(MeshMode::Greedy);
+    assert_eq!(naive.vertices.len(), 256 * 4);
+    assert_eq!(greedy.vertices.len(), 4);
+
+    let merged = q.generate_merged_quads();
+    assert_eq!(merged.len(), 1);
+    assert_eq!(merged[0].size, Pos::from_xyz(16, 1, 16));
+    assert!(greedy.uv.contains(&[16., 16.]));
+    // the stone texture repeats inside its tile of the atlas
+    let ((u0, v0), (u1, v1)) = Stone.get_uv(BlockFace::YP);
+    assert!(greedy.tile.iter().all(|t| *t == [u0, v0, u1, v1]));
+}
+
+#[test]
+fn test_greedy_keeps_block_types_apart() {
+    use super::Stone;
+
+    let mut q = QuadGroup::default();
+    for x in 0..4 {
+        q.insert_quad(Quad::new(
+            Pos::from_xyz(x, 0, 0),
+            BlockType::Stone(Stone),
+            BlockFace::ZP,
+        ));
+    }
+    q.insert_quad(Quad::new(
+        Pos::from_xyz(4, 0, 0),
+        BlockType::None,
+        BlockFace::ZP,
+    ));
+    q.insert_quad(Quad::new(
+        Pos::from_xyz(0, 0, 1),
+        BlockType::Stone(Stone),
+        BlockFace::ZP,
+    ));
+
+    let merged = q.generate_merged_quads();
+    assert_eq!(merged.len(), 3);
+    let area: i64 = merged
+        .iter()
+        .map(|m| m.size.x() * m.size.y() * m.size.z())
+        .sum();
+    assert_eq!(area, 6);
+}
+
+#[test]
+fn test_ambient_occlusion() {
+    use super::Stone;
+
+    assert_eq!(vertex_ao(false, false, false), 3);
+    assert_eq!(vertex_ao(false, false, true), 2);
+    assert_eq!(vertex_ao(true, false, true), 1);
+    assert_eq!(vertex_ao(true, true, false), 0);
+
+    let stone = BlockType::Stone(Stone);
+    let quad = Quad::new(Pos::from_xyz(0, 0, 0), stone, BlockFace::YP);
+    let x_of_vertices = quad.generate_mesh().0.map(|(v, _, _)| v.0);
+
+    // a block next to the top face along +x darkens the two corners at x = 1
+    let ao = quad.ambient_occlusion(|p| p == Pos::from_xyz(1, 1, 0));
+    for (ao, x) in ao.iter().zip(x_of_vertices) {
+        assert_eq!(*ao, if x == 1. { 2 } else { 3 });
+    }
+    // two blocks along +x and +z make a crease
+    let ao = quad.ambient_occlusion(|p| p == Pos::from_xyz(1, 1, 0) || p == Pos::from_xyz(0, 1, 1));
+    assert!(ao.contains(&0));
+
+    // with one dark corner the diagonal goes between the other two
+    let ao = quad.ambient_occlusion(|p| p == Pos::from_xyz(1, 1, 1));
+    let mut mesh = Mesh::default();
+    mesh.push_quad_with_ao(quad.generate_mesh(), quad.tile(), ao);
+    let dark = mesh.ao.iter().position(|b| *b < 1.).unwrap() as u32;
+    assert_eq!(mesh.vertices[dark as usize], [1., 1., 1.]);
+    assert_eq!(
+        mesh.indices.chunks(3).filter(|t| t.contains(&dark)).count(),
+        1
+    );
+    // the winding is kept
+    let n = |t: &[u32]| {
+        let [a, b, c] = [0, 1, 2].map(|k| mesh.vertices[t[k] as usize]);
+        let (u, v) = ([b[0] - a[0], b[2] - a[2]], [c[0] - a[0], c[2] - a[2]]);
+        u[1] * v[0] - u[0] * v[1]
+    };
+    assert!(mesh.indices.chunks(3).all(|t| n(t) > 0.));
+
+    let mut q = QuadGroup::default();
+    for x in 0..6 {
+        for z in 0..6 {
+            q.insert_quad(Quad::new(Pos::from_xyz(x, 0, z), stone, BlockFace::YP));
+        }
+    }
+    let occludes = |p: Pos| p == Pos::from_xyz(2, 1, 2) || p.x() < 0;
+    let merged = q.generate_merged_quads_shaded(&occludes);
+    assert!(merged.len() > 1);
+    for m in merged.iter() {
+        let [a, b, c, d] = m.ambient_occlusion(occludes);
+        if m.size != Pos::from_xyz(1, 1, 1) {
+            assert!(a == b && b == c && c == d);
+        }
+    }
+    // every corner of a rectangle has the occlusion naive meshing gives the corner of its block
+    let naive = q.generate_shaded_mesh(MeshMode::Naive, occludes);
+    let greedy = q.generate_shaded_mesh(MeshMode::Greedy, occludes);
+    assert!(greedy.vertices.len() < naive.vertices.len());
+    for (v, ao) in greedy.vertices.iter().zip(greedy.ao.iter()) {
+        assert!(naive
+            .vertices
+            .iter()
+            .zip(naive.ao.iter())
+            .any(|(w, b)| w == v && b == ao));
+    }
+}
+
+#[test]
+fn test_face_geometry() {
+    use super::Stone;
+
+    let stone = BlockType::Stone(Stone);
+    let dot = |a: Pos, b: Pos| {
+        (0..3)
+            .map(|i| a.to_array()[i] * b.to_array()[i])
+            .sum::<i64>()
+    };
+    for face in BlockFace::iter_all() {
+        let geometry = FaceGeometry::of(face);
+        let normal = face.to_pos();
+        assert_eq!(geometry.normal, normal.to_f32_truple());
+        assert_eq!((dot(geometry.u, normal), dot(geometry.v, normal)), (0, 0));
+        assert_eq!(dot(geometry.u, geometry.v), 0);
+        assert_eq!(
+            geometry.tangent[..3],
+            geometry.u.to_array().map(|v| v as f32)
+        );
+        // the four corners of the side of the unit cube the face points to
+        let corners = geometry.corners();
+        for (k, c) in corners.iter().enumerate() {
+            assert!(c.to_array().iter().all(|v| *v == 0 || *v == 1));
+            assert_eq!(
+                dot(*c, normal),
+                normal.to_array().iter().sum::<i64>().max(0)
+            );
+            assert!(!corners[..k].contains(c));
+        }
+
+        let (axis_u, axis_v) = uv_axes(face);
+        for pos in [Pos::from_xyz(0, 0, 0), Pos::from_xyz(5, -3, 15)] {
+            let (p, i) = Quad::new(pos, stone, face).generate_mesh();
+            let base = pos.to_array().map(|v| v as f32);
+            for (v, n, uv) in p {
+                let v = [v.0, v.1, v.2];
+                assert_eq!(n, geometry.normal);
+                // half a block out from the center of the block
+                let out = (0..3).map(|a| (v[a] - base[a] - 0.5) * [n.0, n.1, n.2][a]);
+                assert_eq!(out.sum::<f32>(), 0.5);
+                assert_eq!(uv, (v[axis_u] - base[axis_u], v[axis_v] - base[axis_v]));
+            }
+            // the winding holds for every way ambient occlusion turns the diagonal
+            for bits in 0..256_u32 {
+                let ao = [0, 2, 4, 6].map(|s| (bits >> s & 3) as u8);
+                let mut mesh = Mesh::default();
+                mesh.push_quad_with_ao((p, i), stone.get_uv(face), ao);
+                mesh.validate().unwrap();
+                assert!(mesh.tangent.iter().all(|t| *t == geometry.tangent));
+            }
+        }
+
+        let mut size = [3, 2, 4];
+        size[face.to_index() / 2] = 1;
+        let merged = MergedQuad::new(
+            Quad::new(Pos::default(), stone, face),
+            Pos::from_array(size),
+        );
+        let mut mesh = Mesh::default();
+        mesh.push_quad(merged.generate_mesh(), merged.quad.tile());
+        mesh.validate().unwrap();
+        assert!(mesh.tangent.iter().all(|t| *t == geometry.tangent));
+    }
+
+    // a whole block in both mesh modes
+    let mut q = QuadGroup::default();
+    for face in BlockFace::iter_all() {
+        q.insert_quad(Quad::new(Pos::from_xyz(1, 2, 3), stone, face));
+    }
+    for mode in [MeshMode::Naive, MeshMode::Greedy] {
+        let mesh = q.generate_mesh_with_mode(mode);
+        assert_eq!(mesh.vertices.len(), 24);
+        mesh.validate().unwrap();
+    }
+
+    // the mistakes validate catches
+    let mesh = q.generate_mesh();
+    let mut wrong = mesh.clone();
+    wrong.indices.swap(0, 1);
+    assert!(wrong.validate().is_err());
+    let mut wrong = mesh.clone();
+    let zn = BlockFace::ZN.to_index() * 4;
+    wrong.normal[zn..zn + 4].fill([0., 0., 1.]);
+    assert!(wrong.validate().is_err());
+    let mut wrong = mesh.clone();
+    wrong.tangent[0][3] = 0.;
+    assert!(wrong.validate().is_err());
+    let mut wrong = mesh;
+    wrong.tangent.pop();
+    assert!(wrong.validate().is_err());
 }
@@ -1,9 +1,11 @@
-use super::{Chunk, CHUNK_SIZE};
+use super::{Chunk, OpenSky, CHUNK_SIZE};
 use crate::chunk::{blocks::BlockId, Pos};
+use serde::{Deserialize, Serialize};
 
+pub mod noise_generator;
 pub mod simple_generator;
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Seed {
     pub seed: u64,
 }
@@ -12,11 +14,29 @@ pub trait ChunkGenerator: ChunkGeneratorBasic {
     fn generate_chunk(&self, chunk_base_position: Pos, seed: Seed) -> Chunk {
         let blocks = self.generate_base_blocks(chunk_base_position, seed);
         Chunk {
-            blocks,
+            blocks: blocks.into(),
             base_pos_of_chunk: chunk_base_position,
+            open_sky: self.open_sky(chunk_base_position, seed),
             ..Default::default()
         }
     }
+
+    /// the columns of the chunk with no generated block above it, see `Chunk::open_sky`
+    fn open_sky(&self, chunk_base_position: Pos, seed: Seed) -> Option<Box<OpenSky>> {
+        let top = chunk_base_position.y() + CHUNK_SIZE as i64;
+        let mut open = Box::new([[false; CHUNK_SIZE]; CHUNK_SIZE]);
+        for (x, column) in open.iter_mut().enumerate() {
+            for (z, open) in column.iter_mut().enumerate() {
+                let h = self.surface_height(
+                    chunk_base_position.x() + x as i64,
+                    chunk_base_position.z() + z as i64,
+                    seed,
+                )?;
+                *open = h < top;
+            }
+        }
+        Some(open)
+    }
 }
 
 pub trait ChunkGeneratorBasic {
@@ -25,6 +45,11 @@ pub trait ChunkGeneratorBasic {
         chunk_base_position: Pos,
         seed: Seed,
     ) -> Box<[[[BlockId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]>;
+
+    /// the height of the highest generated block in this column, `None` if the generator can not tell
+    fn surface_height(&self, _x: i64, _z: i64, _seed: Seed) -> Option<i64> {
+        None
+    }
 }
 
 impl<T> ChunkGenerator for T where T: ChunkGeneratorBasic {}
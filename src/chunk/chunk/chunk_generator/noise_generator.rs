@@ -0,0 +1,160 @@
+use crate::chunk::{blocks::BlockId, chunk::CHUNK_SIZE, Pos};
+
+use super::{ChunkGeneratorBasic, Seed};
+
+/// a heightmap made of fractal value noise, every block at or below the height is stone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseGenerator {
+    pub octaves: u32,
+    /// of the first octave, in noise cells per block
+    pub frequency: f64,
+    /// the largest distance the terrain can reach from `base_height`
+    pub amplitude: f64,
+    pub base_height: i64,
+    /// the amplitude of each octave relative to the previous one
+    pub persistence: f64,
+    /// the frequency of each octave relative to the previous one
+    pub lacunarity: f64,
+}
+
+impl Default for NoiseGenerator {
+    fn default() -> Self {
+        Self {
+            octaves: 4,
+            frequency: 1.0 / 64.0,
+            amplitude: 24.0,
+            base_height: 0,
+            persistence: 0.5,
+            lacunarity: 2.0,
+        }
+    }
+}
+
+/// splitmix64 over the seed, the octave and the lattice point
+fn hash(seed: u64, octave: u32, x: i64, z: i64) -> u64 {
+    let mut h = seed ^ (octave as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    for v in [x as u64, z as u64] {
+        h = h.wrapping_add(v).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        h ^= h >> 31;
+    }
+    h
+}
+
+/// in -1..1
+fn lattice_value(seed: u64, octave: u32, x: i64, z: i64) -> f64 {
+    (hash(seed, octave, x, z) >> 11) as f64 / (1_u64 << 53) as f64 * 2.0 - 1.0
+}
+
+/// smoothly interpolated lattice values, in -1..1
+fn value_noise(seed: u64, octave: u32, x: f64, z: f64) -> f64 {
+    let (x0, z0) = (x.floor(), z.floor());
+    let (fx, fz) = (x - x0, z - z0);
+    let (tx, tz) = (fx * fx * (3.0 - 2.0 * fx), fz * fz * (3.0 - 2.0 * fz));
+    let (x0, z0) = (x0 as i64, z0 as i64);
+
+    let v00 = lattice_value(seed, octave, x0, z0);
+    let v10 = lattice_value(seed, octave, x0 + 1, z0);
+    let v01 = lattice_value(seed, octave, x0, z0 + 1);
+    let v11 = lattice_value(seed, octave, x0 + 1, z0 + 1);
+
+    let a = v00 + (v10 - v00) * tx;
+    let b = v01 + (v11 - v01) * tx;
+    a + (b - a) * tz
+}
+
+impl NoiseGenerator {
+    /// the height of the highest stone block in this column
+    pub fn height_at(&self, x: i64, z: i64, seed: Seed) -> i64 {
+        let mut sum = 0.0;
+        let mut max = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+        for octave in 0..self.octaves {
+            sum += value_noise(
+                seed.seed,
+                octave,
+                x as f64 * frequency,
+                z as f64 * frequency,
+            ) * amplitude;
+            max += amplitude;
+            amplitude *= self.persistence;
+            frequency *= self.lacunarity;
+        }
+        let n = if max > 0.0 { sum / max } else { 0.0 };
+        self.base_height + (n * self.amplitude).round() as i64
+    }
+}
+
+impl ChunkGeneratorBasic for NoiseGenerator {
+    fn generate_base_blocks(
+        &self,
+        chunk_base_position: Pos,
+        seed: Seed,
+    ) -> Box<[[[BlockId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]> {
+        let mut b = Box::new([[[BlockId::from(0_u64); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+
+        for x in 0..CHUNK_SIZE as i64 {
+            for z in 0..CHUNK_SIZE as i64 {
+                let h = self.height_at(
+                    chunk_base_position.x() + x,
+                    chunk_base_position.z() + z,
+                    seed,
+                );
+                let max_y = (h - chunk_base_position.y()).min(CHUNK_SIZE as i64 - 1);
+                for y in 0..=max_y {
+                    b[Pos::from_xyz(x, y, z)] = 1_u64.into();
+                }
+            }
+        }
+        b
+    }
+
+    fn surface_height(&self, x: i64, z: i64, seed: Seed) -> Option<i64> {
+        Some(self.height_at(x, z, seed))
+    }
+}
+
+#[test]
+fn test_noise_generator_deterministic() {
+    let g = NoiseGenerator::default();
+    for base in [Pos::from_xyz(0, 0, 0), Pos::from_xyz(-32, -16, 48)] {
+        let a = g.generate_base_blocks(base, Seed { seed: 12 });
+        let b = g.generate_base_blocks(base, Seed { seed: 12 });
+        assert_eq!(a, b);
+    }
+    let a = g.generate_base_blocks(Pos::from_xyz(0, 0, 0), Seed { seed: 12 });
+    let b = g.generate_base_blocks(Pos::from_xyz(0, 0, 0), Seed { seed: 13 });
+    assert_ne!(a, b);
+}
+
+#[test]
+fn test_noise_generator_height() {
+    use super::ChunkGenerator;
+
+    let g = NoiseGenerator {
+        base_height: 10,
+        amplitude: 8.0,
+        ..Default::default()
+    };
+    let seed = Seed { seed: 5 };
+    for x in -40..40 {
+        for z in -40..40 {
+            let h = g.height_at(x, z, seed);
+            assert!((2..=18).contains(&h));
+        }
+    }
+
+    let h = g.height_at(3, 5, seed);
+    let base = Pos::from_xyz(0, h - 4, 0);
+    let b = g.generate_base_blocks(base, seed);
+    assert_eq!(b[Pos::from_xyz(3, 4, 5)], 1_u64.into());
+    assert_eq!(b[Pos::from_xyz(3, 5, 5)], 0_u64.into());
+
+    // the sky is open above the chunk only over the columns ending in it
+    let open = g.open_sky(base, seed).unwrap();
+    assert!(open[3][5]);
+    assert!(g.open_sky(Pos::from_xyz(0, 32, 0), seed).unwrap()[3][5]);
+    assert!(!g.open_sky(Pos::from_xyz(0, -16, 0), seed).unwrap()[3][5]);
+}
@@ -25,4 +25,8 @@ impl ChunkGeneratorBasic for SimpleGenerator {
             b
         }
     }
+
+    fn surface_height(&self, _x: i64, _z: i64, seed: Seed) -> Option<i64> {
+        Some(seed.seed as i64)
+    }
 }
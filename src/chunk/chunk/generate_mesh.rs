@@ -1,38 +1,184 @@
 use bevy::prelude::Mesh as BevyMesh;
-use bevy::render::mesh::Indices;
-use bevy::render::render_resource::PrimitiveTopology;
+use bevy::render::mesh::{Indices, MeshVertexAttribute};
+use bevy::render::render_resource::{PrimitiveTopology, VertexFormat};
 
-use crate::chunk::blocks::{IdMapping, Mesh};
+use crate::chunk::{
+    blocks::{BlockClient, IdMapping, Mesh, MeshMode, MeshShading, QuadGroup},
+    BlockVisibility, Pos,
+};
 
-use super::Chunk;
+use super::{Chunk, ChunkNeighbours, NeighbourBlocks, CHUNK_SIZE, MAX_LIGHT};
+
+/// the (sky, block) light of every vertex of a chunk mesh from 0 to 1, for shaders that light them differently
+pub const ATTRIBUTE_LIGHT: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Light", 2_930_113_011, VertexFormat::Float32x2);
+
+/// the rectangle of the texture in the atlas of every vertex of a chunk mesh, see `Mesh::tile`
+pub const ATTRIBUTE_TILE: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Tile", 2_930_113_012, VertexFormat::Float32x4);
+
+/// how much darker every light level below `MAX_LIGHT` is
+const LIGHT_FALLOFF: f32 = 0.8;
+
+/// the ambient occlusion and light of a chunk mesh, from the chunk and its neighbours
+pub struct ChunkShading<'a, N> {
+    pub chunk: &'a Chunk,
+    pub id_mapping: &'a IdMapping,
+    pub neighbours: &'a N,
+}
+
+// only references, a derive would need `N: Copy`
+impl<N> Clone for ChunkShading<'_, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<N> Copy for ChunkShading<'_, N> {}
+
+impl<N: NeighbourBlocks> MeshShading for ChunkShading<'_, N> {
+    fn occludes(&self, pos: Pos) -> bool {
+        self.chunk.occludes(self.id_mapping, self.neighbours, pos)
+    }
+
+    fn light(&self, pos: Pos) -> (u8, u8) {
+        self.chunk.light_at(self.neighbours, pos)
+    }
+}
+
+/// how many coordinates of a position relative to a chunk are outside of it
+fn axes_outside(pos: Pos) -> usize {
+    pos.to_array()
+        .iter()
+        .filter(|v| !(0..CHUNK_SIZE as i64).contains(*v))
+        .count()
+}
 
 impl Chunk {
     pub fn generate_mesh(&mut self, id_mapping: &IdMapping) {
+        self.generate_mesh_with_mode(id_mapping, MeshMode::Naive);
+    }
+
+    /// build the opaque, the transparent and the cutout mesh, see `build_meshes`
+    ///
+    /// the shading treats everything outside the chunk as empty and lit by the sky
+    pub fn generate_mesh_with_mode(&mut self, id_mapping: &IdMapping, mode: MeshMode) {
+        self.generate_mesh_with_neighbours(id_mapping, mode, &ChunkNeighbours::default());
+    }
+
+    /// `generate_mesh_with_mode` with the ambient occlusion and light of the neighbours at the border
+    pub fn generate_mesh_with_neighbours<N: NeighbourBlocks>(
+        &mut self,
+        id_mapping: &IdMapping,
+        mode: MeshMode,
+        neighbours: &N,
+    ) {
         if self.quad_group.is_none() {
             self.generate_quad_group(id_mapping);
         }
-        let mesh = {
+        let (mesh, transparent_mesh, cutout_mesh) = {
             if let Some(q) = &self.quad_group {
-                q.generate_mesh()
+                self.build_meshes(q, id_mapping, mode, neighbours)
             } else {
                 panic!()
             }
         };
         self.mesh = Some(mesh);
+        self.transparent_mesh = Some(transparent_mesh);
+        self.cutout_mesh = Some(cutout_mesh);
     }
 
-    pub fn get_bevy_mesh(&mut self) -> Option<BevyMesh> {
-        let mesh: Mesh = match self.mesh.take() {
-            Some(m) => m,
-            None => {
-                return None;
-            }
+    /// the opaque, the transparent and the cutout mesh of a quad group, without touching the chunk
+    ///
+    /// the models of special blocks go into the cutout mesh, so the terrain needs no alpha test
+    pub fn build_meshes<N: NeighbourBlocks>(
+        &self,
+        quads: &QuadGroup,
+        id_mapping: &IdMapping,
+        mode: MeshMode,
+        neighbours: &N,
+    ) -> (Mesh, Mesh, Mesh) {
+        let shading = ChunkShading {
+            chunk: self,
+            id_mapping,
+            neighbours,
+        };
+        let mut cutout_mesh = Mesh::default();
+        quads.push_models(id_mapping, shading, &mut cutout_mesh);
+        (
+            quads.generate_shaded_mesh(mode, shading),
+            quads.generate_shaded_transparent_mesh(shading),
+            cutout_mesh,
+        )
+    }
+
+    /// the (sky, block) light at `pos`, relative to the chunk
+    ///
+    /// full sky light where the chunk or the neighbour is not lit
+    pub fn light_at<N: NeighbourBlocks>(&self, neighbours: &N, pos: Pos) -> (u8, u8) {
+        let light = match axes_outside(pos) {
+            0 => self.light.as_ref().map(|l| l.get(pos)),
+            1 => neighbours.get_light(pos),
+            _ => None,
+        };
+        light.unwrap_or((MAX_LIGHT, 0))
+    }
+
+    /// if the block at `pos`, relative to the chunk, darkens the corners of the faces next to it
+    ///
+    /// only blocks outside the chunk along one axis are looked up in `neighbours`
+    pub fn occludes<N: NeighbourBlocks>(
+        &self,
+        id_mapping: &IdMapping,
+        neighbours: &N,
+        pos: Pos,
+    ) -> bool {
+        let id = match axes_outside(pos) {
+            0 => self.get_pos_in_chunk(pos),
+            1 => neighbours.get_block(pos),
+            _ => None,
         };
-        let mut m = BevyMesh::new(PrimitiveTopology::TriangleList);
-        m.insert_attribute(BevyMesh::ATTRIBUTE_POSITION, mesh.vertices);
-        m.insert_attribute(BevyMesh::ATTRIBUTE_NORMAL, mesh.normal);
-        m.insert_attribute(BevyMesh::ATTRIBUTE_UV_0, mesh.uv);
-        m.set_indices(Some(Indices::U32(mesh.indices)));
-        Some(m)
+        id.and_then(|id| id.get_block_type(id_mapping))
+            .is_some_and(|b| b.visibility() == BlockVisibility::Opaque)
     }
+
+    pub fn get_bevy_mesh(&mut self) -> Option<BevyMesh> {
+        self.mesh.take().map(to_bevy_mesh)
+    }
+
+    pub fn get_transparent_bevy_mesh(&mut self) -> Option<BevyMesh> {
+        self.transparent_mesh.take().map(to_bevy_mesh)
+    }
+
+    pub fn get_cutout_bevy_mesh(&mut self) -> Option<BevyMesh> {
+        self.cutout_mesh.take().map(to_bevy_mesh)
+    }
+}
+
+fn to_bevy_mesh(mesh: Mesh) -> BevyMesh {
+    let mut m = BevyMesh::new(PrimitiveTopology::TriangleList);
+    m.insert_attribute(BevyMesh::ATTRIBUTE_POSITION, mesh.vertices);
+    m.insert_attribute(BevyMesh::ATTRIBUTE_NORMAL, mesh.normal);
+    m.insert_attribute(BevyMesh::ATTRIBUTE_UV_0, mesh.uv);
+    m.insert_attribute(ATTRIBUTE_TILE, mesh.tile);
+    m.insert_attribute(BevyMesh::ATTRIBUTE_TANGENT, mesh.tangent);
+    // the ambient occlusion and the brighter of the two lights darken the base color
+    let color = mesh
+        .ao
+        .iter()
+        .zip(mesh.light.iter())
+        .map(|(ao, [sky, block])| {
+            let b = ao * LIGHT_FALLOFF.powi((MAX_LIGHT - sky.max(block)) as i32);
+            [b, b, b, 1.]
+        })
+        .collect::<Vec<_>>();
+    m.insert_attribute(BevyMesh::ATTRIBUTE_COLOR, color);
+    let light = mesh
+        .light
+        .iter()
+        .map(|l| l.map(|v| v as f32 / MAX_LIGHT as f32))
+        .collect::<Vec<_>>();
+    m.insert_attribute(ATTRIBUTE_LIGHT, light);
+    m.set_indices(Some(Indices::U32(mesh.indices)));
+    m
 }
@@ -1,43 +1,130 @@
-use super::Chunk;
+use super::{Chunk, ChunkClass, ChunkNeighbours, NeighbourBlocks};
 use crate::chunk::{
-    blocks::{BlockClient, IdMapping, Quad, QuadGroup},
-    BlockFace, BlockVisibility,
+    blocks::{BlockClient, BlockId, BlockType, IdMapping, NeighbourBlock, Quad, QuadGroup},
+    BlockFace, BlockVisibility, Pos,
 };
 
 impl Chunk {
     /// generate the QuadGroup
     ///
+    /// everything outside the chunk is treated as empty
     pub fn generate_quad_group(&mut self, id_mapping: &IdMapping) {
+        let quads = self.build_quad_group(id_mapping, &ChunkNeighbours::default());
+        self.set_quad_group(quads);
+    }
+
+    /// generate the QuadGroup, culling the faces on the border against the loaded neighbours
+    pub fn generate_quad_group_with_neighbours<N: NeighbourBlocks>(
+        &mut self,
+        id_mapping: &IdMapping,
+        neighbours: &N,
+    ) {
+        let quads = self.build_quad_group(id_mapping, neighbours);
+        self.set_quad_group(quads);
+    }
+
+    /// replace the QuadGroup, the meshes of the old one are dropped so they are built again
+    pub fn set_quad_group(&mut self, quads: QuadGroup) {
+        self.quad_group = Some(quads);
+        self.mesh = None;
+        self.transparent_mesh = None;
+        self.cutout_mesh = None;
+    }
+
+    /// build a QuadGroup without touching the chunk
+    ///
+    /// blocks in unloaded neighbours are treated as empty
+    pub fn build_quad_group<N: NeighbourBlocks>(
+        &self,
+        id_mapping: &IdMapping,
+        neighbours: &N,
+    ) -> QuadGroup {
         let mut quads = QuadGroup::default();
+        if self.class == ChunkClass::Empty || self.is_occluded(neighbours) {
+            return quads;
+        }
         for (pos, block) in self.iter() {
             //dbg!(pos);
             let block = match block.get_block_type(id_mapping) {
                 Some(v) => v,
                 None => continue,
             };
-            match block.visibility() {
-                BlockVisibility::Opaque => {}
-                _ => continue,
-            };
+            let visibility = block.visibility();
+            if visibility == BlockVisibility::Empty {
+                continue;
+            }
+            let mut hidden = 0;
             for face in BlockFace::iter_all() {
                 // dbg!(face);
-                let neighber_visibility = match self.get_pos_in_chunk(pos + face) {
-                    Some(v) => match v.get_block_type(id_mapping) {
-                        Some(v) => v.visibility(),
-                        None => BlockVisibility::Empty,
-                    },
-                    None => BlockVisibility::Empty,
-                };
-                match neighber_visibility {
-                    BlockVisibility::Opaque => {}
-                    _ => {
-                        quads.insert_quad(Quad::new(pos, block, face));
-                    }
+                let neighber = match self.get_pos_in_chunk(pos + face) {
+                    Some(v) => Some(v),
+                    None => neighbours.get_block(pos + face),
                 };
+                let neighber = neighber
+                    .and_then(|v| v.get_block_type(id_mapping))
+                    .unwrap_or(BlockType::None);
+                if neighber.visibility() == BlockVisibility::Opaque {
+                    hidden |= 1 << face.to_index();
+                }
+                if block.shows_face_to(neighber) {
+                    quads.insert_quad(Quad::new(pos, block, face));
+                }
+            }
+            if visibility == BlockVisibility::Special {
+                quads.insert_special(pos, block, hidden);
             }
         }
-        self.quad_group = Some(quads);
-        self.mesh_up_to_date = false;
+        quads
+    }
+
+    /// set a block and patch the quad group in place instead of rebuilding it
+    ///
+    /// `outside` gives the blocks outside the chunk, relative to the chunk, like `ChunkNeighbours::get_block`
+    ///
+    /// returns the previous block, or `None` if the position is outside the chunk
+    pub fn set_block_incremental<F>(
+        &mut self,
+        pos_in_chunk: Pos,
+        id: BlockId,
+        id_mapping: &IdMapping,
+        outside: F,
+    ) -> Option<BlockId>
+    where
+        F: Fn(Pos) -> Option<BlockId>,
+    {
+        let old = self.get_pos_in_chunk(pos_in_chunk)?;
+        if old == id {
+            return Some(old);
+        }
+        let mut quads = match self.quad_group.take() {
+            Some(q) => q,
+            None => return self.set_block(pos_in_chunk, id),
+        };
+        self.blocks.set(pos_in_chunk, id);
+        self.class = ChunkClass::Mixed;
+        self.modified = true;
+        self.version += 1;
+
+        let block_type = |id: Option<BlockId>| {
+            id.and_then(|id| id.get_block_type(id_mapping))
+                .unwrap_or(BlockType::None)
+        };
+        let mut neighbours = [NeighbourBlock::Outside(BlockType::None); 6];
+        for face in BlockFace::iter_all() {
+            let p = pos_in_chunk + face;
+            neighbours[face.to_index()] = match self.get_pos_in_chunk(p) {
+                Some(v) => NeighbourBlock::Inside(block_type(Some(v))),
+                None => NeighbourBlock::Outside(block_type(outside(p))),
+            };
+        }
+        quads.update_block(
+            pos_in_chunk,
+            block_type(Some(old)),
+            block_type(Some(id)),
+            neighbours,
+        );
+        self.set_quad_group(quads);
+        Some(old)
     }
 
     pub fn get_quad_group(&self) -> Option<&QuadGroup> {
@@ -54,3 +141,154 @@ impl Chunk {
         self.get_quad_group().unwrap()
     }
 }
+
+#[test]
+fn test_cull_against_neighbours() {
+    use crate::chunk::blocks::BlockId;
+
+    let id_mapping = IdMapping::default();
+    let stone = BlockId::from(1_u64);
+    let mut a = Chunk::new_filled_with_id(stone);
+    let b = Chunk::new_filled_with_id(stone);
+
+    a.generate_quad_group(&id_mapping);
+    assert_eq!(a.get_quad_group().unwrap().iter().count(), 16 * 16 * 6);
+    a.generate_mesh(&id_mapping);
+    assert!(a.mesh.is_some());
+
+    let mut neighbours = ChunkNeighbours::default();
+    neighbours.chunks[BlockFace::XP.to_index()] = Some(&b);
+    a.generate_quad_group_with_neighbours(&id_mapping, &neighbours);
+    let quads = a.get_quad_group().unwrap();
+    assert_eq!(quads.iter().count(), 16 * 16 * 5);
+    assert!(quads.iter().all(|q| q.face != BlockFace::XP));
+    // the mesh still showing the culled faces is not uploaded again
+    assert!(a.mesh.is_none());
+}
+
+#[test]
+fn test_incremental_update() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let id_mapping = IdMapping::default();
+    let mut r = StdRng::seed_from_u64(42);
+    let random_chunk = |r: &mut StdRng| {
+        let mut c = Chunk::default();
+        for (pos, _) in Chunk::default().iter() {
+            c.blocks.set(pos, (r.gen_range(0..2_u64)).into());
+        }
+        c
+    };
+    let mut c = random_chunk(&mut r);
+    let neighbour = random_chunk(&mut r);
+    let mut neighbours = ChunkNeighbours::default();
+    neighbours.chunks[BlockFace::YN.to_index()] = Some(&neighbour);
+    c.generate_quad_group_with_neighbours(&id_mapping, &neighbours);
+
+    for _ in 0..300 {
+        let pos = Pos::from_xyz(r.gen_range(0..16), r.gen_range(0..16), r.gen_range(0..16));
+        let id = BlockId::from(r.gen_range(0..3_u64));
+        c.set_block_incremental(pos, id, &id_mapping, |p| neighbours.get_block(p));
+
+        let full = c.build_quad_group(&id_mapping, &neighbours);
+        assert_eq!(c.get_quad_group().unwrap(), &full);
+    }
+}
+
+#[test]
+fn test_transparent_culling() {
+    use crate::chunk::blocks::BlockRegistry;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let registry = BlockRegistry::from_json(
+        r#"{ "blocks": [
+            { "name": "glass", "visibility": "Transparent", "textures": { "all": "glass" } },
+            { "name": "water", "visibility": "Transparent", "textures": { "all": "water" } }
+        ] }"#,
+    )
+    .unwrap();
+    let id_mapping = registry.id_mapping();
+    let id = |name| id_mapping.id_of(registry.get(name).unwrap()).unwrap();
+    let face_between = |a, b| {
+        let mut c = Chunk::default();
+        c.set_block(Pos::from_xyz(1, 1, 1), id(a));
+        c.set_block(Pos::from_xyz(2, 1, 1), id(b));
+        c.build_quad_group(&id_mapping, &ChunkNeighbours::default())
+            .iter()
+            .any(|q| q.pos == Pos::from_xyz(1, 1, 1) && q.face == BlockFace::XP)
+    };
+    assert!(!face_between("glass", "glass"));
+    assert!(face_between("glass", "water"));
+    assert!(!face_between("glass", "stone"));
+    assert!(face_between("stone", "glass"));
+
+    // the incremental update follows the same rules
+    let mut r = StdRng::seed_from_u64(7);
+    let mut c = Chunk::default();
+    c.generate_quad_group(&id_mapping);
+    for _ in 0..500 {
+        let pos = Pos::from_xyz(r.gen_range(0..4), r.gen_range(0..4), r.gen_range(0..4));
+        let id = BlockId::from(r.gen_range(0..id_mapping.mapping.len()));
+        c.set_block_incremental(pos, id, &id_mapping, |_| None);
+        let full = c.build_quad_group(&id_mapping, &ChunkNeighbours::default());
+        assert_eq!(c.get_quad_group().unwrap(), &full);
+    }
+}
+
+#[test]
+fn test_special_block_culling() {
+    use crate::chunk::blocks::{BlockRegistry, BlockRotation, Mesh};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let registry = BlockRegistry::from_json(
+        r#"{ "blocks": [{
+            "name": "slab",
+            "visibility": "Special",
+            "textures": { "all": "slab" },
+            "rotations": "all",
+            "model": [{ "box": { "from": [0, 0, 0], "to": [16, 8, 16] } }]
+        }] }"#,
+    )
+    .unwrap();
+    let id_mapping = registry.id_mapping();
+    let slab = id_mapping.id_of(registry.get("slab").unwrap()).unwrap();
+    let vertices = |c: &Chunk| {
+        let mut mesh = Mesh::default();
+        c.build_quad_group(&id_mapping, &ChunkNeighbours::default())
+            .push_models(&id_mapping, (), &mut mesh);
+        mesh.vertices.len()
+    };
+
+    // the bottom of a slab on stone is hidden, not the top under stone or the side next to another slab
+    let mut c = Chunk::default();
+    c.set_block(Pos::from_xyz(1, 1, 1), slab);
+    assert_eq!(vertices(&c), 6 * 4);
+    c.set_block(Pos::from_xyz(1, 0, 1), 1_u64.into());
+    assert_eq!(vertices(&c), 5 * 4);
+    c.set_block(Pos::from_xyz(2, 1, 1), slab);
+    c.set_block(Pos::from_xyz(1, 2, 1), 1_u64.into());
+    assert_eq!(vertices(&c), (5 + 6) * 4);
+    // the stone shows every face, the slabs do not fill their blocks
+    let quads = c.build_quad_group(&id_mapping, &ChunkNeighbours::default());
+    assert_eq!(quads.len(), 2 * 6);
+
+    // the top of an upside down slab is hidden by the stone above
+    let top_slab = registry
+        .rotated(registry.get("slab").unwrap(), BlockRotation::new(0, true))
+        .unwrap();
+    c.set_block(Pos::from_xyz(1, 0, 1), 0_u64.into());
+    c.set_block(Pos::from_xyz(1, 1, 1), id_mapping.id_of(top_slab).unwrap());
+    assert_eq!(vertices(&c), (5 + 6) * 4);
+
+    // the incremental update keeps the hidden faces right
+    let mut r = StdRng::seed_from_u64(22);
+    let mut c = Chunk::default();
+    c.generate_quad_group(&id_mapping);
+    for _ in 0..500 {
+        let pos = Pos::from_xyz(r.gen_range(0..4), r.gen_range(0..4), r.gen_range(0..4));
+        let id = BlockId::from(r.gen_range(0..id_mapping.mapping.len()));
+        c.set_block_incremental(pos, id, &id_mapping, |_| None);
+        let full = c.build_quad_group(&id_mapping, &ChunkNeighbours::default());
+        assert_eq!(c.get_quad_group().unwrap(), &full);
+    }
+}
@@ -52,7 +52,8 @@ pub fn a() {
     for z in 0..CHUNK_SIZE {
         for y in 0..CHUNK_SIZE {
             for x in 0..CHUNK_SIZE {
-                c.blocks[x][y][z] = id.into();
+                c.blocks
+                    .set(Pos::from_xyz(x as i64, y as i64, z as i64), id.into());
                 id += 1;
             }
         }
@@ -0,0 +1,424 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::chunk::{
+    blocks::{BlockClient, BlockType, IdMapping},
+    BlockFace, BlockVisibility, Pos,
+};
+
+use super::{index_of, Chunk, CHUNK_SIZE};
+
+/// the light level of open sky and of the brightest light source
+pub const MAX_LIGHT: u8 = 15;
+
+/// the two kinds of light every block has a level of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LightChannel {
+    /// comes down from the open sky above the generated surface, does not get darker going straight down
+    Sky,
+    /// comes from blocks with an emission
+    Block,
+}
+
+impl LightChannel {
+    pub const ALL: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+}
+
+/// the sky and block light of every block of a chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkLight {
+    /// the sky light in the high and the block light in the low four bits, in the order of `ChunkBlocks`
+    levels: Box<[u8; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE]>,
+}
+
+impl Default for ChunkLight {
+    fn default() -> Self {
+        Self {
+            levels: Box::new([0; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE]),
+        }
+    }
+}
+
+impl ChunkLight {
+    /// (sky, block)
+    pub fn get(&self, pos_in_chunk: Pos) -> (u8, u8) {
+        let v = self.levels[index_of(pos_in_chunk)];
+        (v >> 4, v & 0xf)
+    }
+
+    pub fn get_channel(&self, pos_in_chunk: Pos, channel: LightChannel) -> u8 {
+        let (sky, block) = self.get(pos_in_chunk);
+        match channel {
+            LightChannel::Sky => sky,
+            LightChannel::Block => block,
+        }
+    }
+
+    pub fn set_channel(&mut self, pos_in_chunk: Pos, channel: LightChannel, level: u8) {
+        let v = &mut self.levels[index_of(pos_in_chunk)];
+        let level = level.min(MAX_LIGHT);
+        *v = match channel {
+            LightChannel::Sky => (*v & 0xf) | level << 4,
+            LightChannel::Block => (*v & 0xf0) | level,
+        };
+    }
+}
+
+/// the chunks light spreads through, by their base position
+pub trait LightChunks {
+    fn chunk(&self, base_pos_of_chunk: Pos) -> Option<&Chunk>;
+    fn chunk_mut(&mut self, base_pos_of_chunk: Pos) -> Option<&mut Chunk>;
+}
+
+impl LightChunks for HashMap<Pos, Chunk> {
+    fn chunk(&self, base_pos_of_chunk: Pos) -> Option<&Chunk> {
+        self.get(&base_pos_of_chunk)
+    }
+
+    fn chunk_mut(&mut self, base_pos_of_chunk: Pos) -> Option<&mut Chunk> {
+        self.get_mut(&base_pos_of_chunk)
+    }
+}
+
+/// the level one block further in the direction `face`
+fn spread(level: u8, channel: LightChannel, face: BlockFace) -> u8 {
+    if channel == LightChannel::Sky && face == BlockFace::YN && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+/// flood fill over world positions
+struct LightUpdate<'a, C> {
+    chunks: &'a mut C,
+    id_mapping: &'a IdMapping,
+    /// the base positions of the chunks whose light changed
+    changed: HashSet<Pos>,
+}
+
+impl<C: LightChunks> LightUpdate<'_, C> {
+    /// `None` if the chunk is not loaded
+    fn block(&self, world_pos: Pos) -> Option<BlockType> {
+        let chunk = self.chunks.chunk(Chunk::base_pos_of(world_pos))?;
+        let id = chunk.get_pos_in_chunk(Chunk::pos_in_chunk_of(world_pos))?;
+        Some(
+            id.get_block_type(self.id_mapping)
+                .unwrap_or(BlockType::None),
+        )
+    }
+
+    /// if light can get into the block
+    fn passes(&self, world_pos: Pos) -> bool {
+        matches!(self.block(world_pos), Some(b) if b.visibility() != BlockVisibility::Opaque)
+    }
+
+    /// the level the block has on its own, from its emission or from the open sky above it
+    fn source(&self, world_pos: Pos, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Block => self.block(world_pos).map_or(0, |b| b.emission()),
+            LightChannel::Sky => {
+                // a loaded chunk above gives its light by propagation
+                let open = match self.block(world_pos + BlockFace::YP) {
+                    Some(_) => false,
+                    None => self.open_sky(world_pos),
+                };
+                if open && self.passes(world_pos) {
+                    MAX_LIGHT
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    /// if the generator left nothing above this column of the chunk
+    fn open_sky(&self, world_pos: Pos) -> bool {
+        let p = Chunk::pos_in_chunk_of(world_pos);
+        let chunk = self.chunks.chunk(Chunk::base_pos_of(world_pos));
+        matches!(chunk.and_then(|c| c.open_sky.as_ref()), Some(o) if o[p.x() as usize][p.z() as usize])
+    }
+
+    fn get(&self, world_pos: Pos, channel: LightChannel) -> u8 {
+        self.chunks
+            .chunk(Chunk::base_pos_of(world_pos))
+            .and_then(|c| c.light.as_ref())
+            .map_or(0, |l| {
+                l.get_channel(Chunk::pos_in_chunk_of(world_pos), channel)
+            })
+    }
+
+    /// chunks that are not lit yet are left alone, `light_chunk` takes the light of their neighbours later
+    fn set(&mut self, world_pos: Pos, channel: LightChannel, level: u8) {
+        if self.get(world_pos, channel) == level {
+            return;
+        }
+        let base = Chunk::base_pos_of(world_pos);
+        if let Some(light) = self.chunks.chunk_mut(base).and_then(|c| c.light.as_mut()) {
+            light.set_channel(Chunk::pos_in_chunk_of(world_pos), channel, level);
+            self.changed.insert(base);
+        }
+    }
+
+    /// spread the light of the queued blocks to every block it can reach
+    fn propagate(&mut self, mut queue: VecDeque<(Pos, LightChannel)>) {
+        while let Some((p, channel)) = queue.pop_front() {
+            let level = self.get(p, channel);
+            if level <= 1 {
+                continue;
+            }
+            for face in BlockFace::iter_all() {
+                let n = p + face;
+                let l = spread(level, channel, face);
+                if self.get(n, channel) < l && self.passes(n) {
+                    self.set(n, channel, l);
+                    queue.push_back((n, channel));
+                }
+            }
+        }
+    }
+
+    /// darken everything that was lit by the queued blocks, which are already set to 0 and were at the given level
+    ///
+    /// returns the blocks that are still lit next to the darkened ones, `propagate` fills the gap from them
+    fn remove(
+        &mut self,
+        mut queue: VecDeque<(Pos, LightChannel, u8)>,
+    ) -> VecDeque<(Pos, LightChannel)> {
+        let mut relight = VecDeque::new();
+        while let Some((p, channel, level)) = queue.pop_front() {
+            for face in BlockFace::iter_all() {
+                let n = p + face;
+                let l = self.get(n, channel);
+                if l == 0 {
+                    continue;
+                }
+                let lit_by_p = l < level
+                    || (channel == LightChannel::Sky
+                        && face == BlockFace::YN
+                        && level == MAX_LIGHT);
+                if lit_by_p {
+                    self.set(n, channel, 0);
+                    queue.push_back((n, channel, l));
+                    let source = self.source(n, channel);
+                    if source > 0 {
+                        self.set(n, channel, source);
+                        relight.push_back((n, channel));
+                    }
+                } else {
+                    relight.push_back((n, channel));
+                }
+            }
+        }
+        relight
+    }
+}
+
+/// light a newly loaded chunk from its own sources and the light of its neighbours, and spread it into them
+///
+/// while the chunk above is not loaded the sky is open above the columns in `Chunk::open_sky`,
+/// the chunk below loses the sky light it got that way if the new chunk covers it
+///
+/// returns the base positions of the chunks whose light changed
+pub fn light_chunk<C: LightChunks>(
+    chunks: &mut C,
+    id_mapping: &IdMapping,
+    base_pos_of_chunk: Pos,
+) -> HashSet<Pos> {
+    let mut update = LightUpdate {
+        chunks,
+        id_mapping,
+        changed: HashSet::new(),
+    };
+    match update.chunks.chunk_mut(base_pos_of_chunk) {
+        Some(c) => c.light = Some(ChunkLight::default()),
+        None => return update.changed,
+    }
+    update.changed.insert(base_pos_of_chunk);
+
+    let size = CHUNK_SIZE as i64;
+    let mut queue = VecDeque::new();
+    for pos in Pos::from_xyz(0, 0, 0).iter_cube(size - 1, size - 1, size - 1) {
+        let p = base_pos_of_chunk + pos;
+        for channel in LightChannel::ALL {
+            let source = update.source(p, channel);
+            if source > 0 {
+                update.set(p, channel, source);
+                queue.push_back((p, channel));
+            }
+        }
+        // the blocks of the neighbours touching this chunk
+        for face in Chunk::border_faces(pos) {
+            for channel in LightChannel::ALL {
+                if update.get(p + face, channel) > 0 {
+                    queue.push_back((p + face, channel));
+                }
+            }
+        }
+    }
+    update.propagate(queue);
+
+    let mut removed = VecDeque::new();
+    for x in 0..size {
+        for z in 0..size {
+            let p = base_pos_of_chunk + Pos::from_xyz(x, -1, z);
+            let above = update.get(p + BlockFace::YP, LightChannel::Sky);
+            if update.get(p, LightChannel::Sky) == MAX_LIGHT && above != MAX_LIGHT {
+                update.set(p, LightChannel::Sky, 0);
+                removed.push_back((p, LightChannel::Sky, MAX_LIGHT));
+            }
+        }
+    }
+    let relight = update.remove(removed);
+    update.propagate(relight);
+    update.changed
+}
+
+/// update the light around a block that was placed or removed, nothing happens if its chunk is not lit
+///
+/// returns the base positions of the chunks whose light changed
+pub fn update_light<C: LightChunks>(
+    chunks: &mut C,
+    id_mapping: &IdMapping,
+    world_pos: Pos,
+) -> HashSet<Pos> {
+    let mut update = LightUpdate {
+        chunks,
+        id_mapping,
+        changed: HashSet::new(),
+    };
+    let lit = update
+        .chunks
+        .chunk(Chunk::base_pos_of(world_pos))
+        .is_some_and(|c| c.light.is_some());
+    if !lit {
+        return update.changed;
+    }
+    for channel in LightChannel::ALL {
+        let old = update.get(world_pos, channel);
+        update.set(world_pos, channel, 0);
+        let mut relight = update.remove(VecDeque::from([(world_pos, channel, old)]));
+        let source = update.source(world_pos, channel);
+        if source > 0 {
+            update.set(world_pos, channel, source);
+            relight.push_back((world_pos, channel));
+        }
+        for face in BlockFace::iter_all() {
+            relight.push_back((world_pos + face, channel));
+        }
+        update.propagate(relight);
+    }
+    update.changed
+}
+
+#[test]
+fn test_light_propagation() {
+    use crate::chunk::blocks::{BlockId, BlockRegistry};
+
+    let registry = BlockRegistry::from_json(
+        r#"{ "blocks": [
+            { "name": "lamp", "visibility": "Opaque", "textures": { "all": "lamp" }, "emission": 14 }
+        ] }"#,
+    )
+    .unwrap();
+    let id_mapping = registry.id_mapping();
+    let lamp = id_mapping.id_of(registry.get("lamp").unwrap()).unwrap();
+    let stone = BlockId::from(1_u64);
+
+    // two chunks on top of each other, the lower one with a stone roof
+    let mut chunks = HashMap::new();
+    let lower = Pos::from_xyz(0, 0, 0);
+    let upper = Pos::from_xyz(0, 16, 0);
+    let open_sky = Some(Box::new([[true; 16]; 16]));
+    let mut c = Chunk {
+        open_sky: open_sky.clone(),
+        ..Default::default()
+    };
+    for x in 0..16 {
+        for z in 0..16 {
+            c.set_block(Pos::from_xyz(x, 15, z), stone);
+        }
+    }
+    chunks.insert(lower, c);
+    light_chunk(&mut chunks, &id_mapping, lower);
+    let light = |chunks: &HashMap<Pos, Chunk>, p: Pos| {
+        chunks[&Chunk::base_pos_of(p)]
+            .light
+            .as_ref()
+            .unwrap()
+            .get(Chunk::pos_in_chunk_of(p))
+    };
+    // nothing above yet, the generator says the sky is open above the roof and it is dark below
+    assert_eq!(light(&chunks, Pos::from_xyz(3, 14, 3)), (0, 0));
+
+    // without the generator telling, an unloaded chunk above gives no sky
+    let mut buried = HashMap::new();
+    buried.insert(lower, Chunk::default());
+    light_chunk(&mut buried, &id_mapping, lower);
+    assert_eq!(light(&buried, Pos::from_xyz(3, 15, 3)), (0, 0));
+
+    chunks.insert(
+        upper,
+        Chunk {
+            base_pos_of_chunk: upper,
+            open_sky,
+            ..Default::default()
+        },
+    );
+    let changed = light_chunk(&mut chunks, &id_mapping, upper);
+    assert!(changed.contains(&upper));
+    assert_eq!(light(&chunks, Pos::from_xyz(3, 31, 3)), (MAX_LIGHT, 0));
+    assert_eq!(light(&chunks, Pos::from_xyz(3, 16, 3)), (MAX_LIGHT, 0));
+
+    // a hole in the roof lets the sky straight down and around
+    let hole = Pos::from_xyz(8, 15, 8);
+    chunks
+        .get_mut(&lower)
+        .unwrap()
+        .set_block(hole, 0_u64.into());
+    let changed = update_light(&mut chunks, &id_mapping, hole);
+    assert!(changed.contains(&lower));
+    assert_eq!(light(&chunks, Pos::from_xyz(8, 0, 8)), (MAX_LIGHT, 0));
+    assert_eq!(light(&chunks, Pos::from_xyz(10, 0, 8)), (MAX_LIGHT - 2, 0));
+    assert_eq!(light(&chunks, Pos::from_xyz(8, 14, 11)), (MAX_LIGHT - 3, 0));
+
+    // closing it again makes the lower chunk dark
+    chunks.get_mut(&lower).unwrap().set_block(hole, stone);
+    update_light(&mut chunks, &id_mapping, hole);
+    assert!(Pos::from_xyz(0, 0, 0)
+        .iter_cube(15, 14, 15)
+        .all(|p| light(&chunks, p) == (0, 0)));
+
+    // a lamp lights the blocks around it, also in the other chunk
+    let at = Pos::from_xyz(2, 14, 2);
+    chunks.get_mut(&lower).unwrap().set_block(at, lamp);
+    update_light(&mut chunks, &id_mapping, at);
+    assert_eq!(light(&chunks, at).1, 14);
+    assert_eq!(light(&chunks, Pos::from_xyz(2, 10, 2)).1, 10);
+    assert_eq!(light(&chunks, Pos::from_xyz(2, 13, 5)).1, 10);
+    // the roof is opaque, the light goes through the hole next to it
+    let hole = Pos::from_xyz(3, 15, 2);
+    chunks
+        .get_mut(&lower)
+        .unwrap()
+        .set_block(hole, 0_u64.into());
+    update_light(&mut chunks, &id_mapping, hole);
+    assert_eq!(light(&chunks, Pos::from_xyz(3, 16, 2)), (MAX_LIGHT, 11));
+
+    chunks.get_mut(&lower).unwrap().set_block(at, 0_u64.into());
+    update_light(&mut chunks, &id_mapping, at);
+    assert!(chunks
+        .keys()
+        .flat_map(|base| base.iter_cube(15, 15, 15))
+        .all(|p| light(&chunks, p).1 == 0));
+
+    // the incremental updates give the same light as lighting everything again
+    let mut fresh = chunks.clone();
+    for c in fresh.values_mut() {
+        c.light = None;
+    }
+    light_chunk(&mut fresh, &id_mapping, lower);
+    light_chunk(&mut fresh, &id_mapping, upper);
+    for base in [lower, upper] {
+        assert_eq!(chunks[&base].light, fresh[&base].light);
+    }
+}
@@ -3,8 +3,8 @@ use std::fmt::Debug;
 use bevy::prelude::Component;
 
 use super::{
-    blocks::{BlockId, Mesh, QuadGroup},
-    Pos,
+    blocks::{BlockClient, BlockId, IdMapping, Mesh, QuadGroup},
+    BlockFace, BlockVisibility, Pos,
 };
 
 mod iter;
@@ -16,39 +16,260 @@ pub use generate_quad_group::*;
 mod generate_mesh;
 pub use generate_mesh::*;
 
+mod palette;
+pub use palette::*;
+
+mod neighbours;
+pub use neighbours::*;
+
 mod chunk_generator;
 pub use chunk_generator::*;
 
+mod light;
+pub use light::*;
+
 pub const CHUNK_SIZE: usize = 16_usize;
 
+/// a flag per column of a chunk, indexed by x then z
+pub type OpenSky = [[bool; CHUNK_SIZE]; CHUNK_SIZE];
+
+/// what a chunk is made of, decides if it needs meshing at all
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkClass {
+    /// may contain anything, also used before the chunk is classified
+    #[default]
+    Mixed,
+    /// only empty blocks, never has a mesh
+    Empty,
+    /// only opaque blocks, only has a mesh where a neighbour is not solid
+    Solid,
+}
+
 #[derive(Debug, Component, Default, Clone)]
 pub struct Chunk {
     pub base_pos_of_chunk: Pos,
-    pub blocks: Box<[[[BlockId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]>,
+    pub blocks: ChunkBlocks,
+    pub class: ChunkClass,
+    /// the blocks were edited since the chunk was generated or last saved
+    pub modified: bool,
+    /// bumped whenever the quad group is invalidated, results of meshing jobs for older versions are dropped
+    pub version: u64,
     pub mesh: Option<Mesh>,
+    /// the faces of transparent blocks, drawn with an alpha blended material
+    pub transparent_mesh: Option<Mesh>,
+    /// the models of special blocks, drawn with an alpha tested material
+    pub cutout_mesh: Option<Mesh>,
     pub quad_group: Option<QuadGroup>,
-    pub quad_group_changed: bool,
-    pub mesh_up_to_date: bool,
+    /// `None` until `light_chunk` lit the chunk, meshes are fully lit by the sky until then
+    pub light: Option<ChunkLight>,
+    /// the columns with nothing generated above the chunk, from the generator,
+    /// `None` if the generator can not tell, then no sky light comes in from an unloaded chunk above
+    pub open_sky: Option<Box<OpenSky>>,
 }
 
 impl Chunk {
+    /// set `class` from the visibility of the blocks in the palette
+    pub fn classify(&mut self, id_mapping: &IdMapping) {
+        self.blocks.compact();
+        let visibility = |id: &BlockId| match id.get_block_type(id_mapping) {
+            Some(v) => v.visibility(),
+            None => BlockVisibility::Empty,
+        };
+        let palette = self.blocks.palette();
+        self.class = if palette
+            .iter()
+            .all(|id| visibility(id) == BlockVisibility::Empty)
+        {
+            ChunkClass::Empty
+        } else if palette
+            .iter()
+            .all(|id| visibility(id) == BlockVisibility::Opaque)
+        {
+            ChunkClass::Solid
+        } else {
+            ChunkClass::Mixed
+        };
+    }
+
+    /// a solid chunk with solid chunks on all six sides has no visible face
+    pub fn is_occluded<N: NeighbourBlocks>(&self, neighbours: &N) -> bool {
+        self.class == ChunkClass::Solid && BlockFace::iter_all().all(|f| neighbours.is_solid(f))
+    }
+
+    /// the layer on this face has only empty blocks, so the neighbour on that side meshes
+    /// the same whether this chunk is loaded or not
+    pub fn face_is_empty(&self, face: BlockFace, id_mapping: &IdMapping) -> bool {
+        match self.class {
+            ChunkClass::Empty => return true,
+            ChunkClass::Solid => return false,
+            ChunkClass::Mixed => {}
+        }
+        let max = CHUNK_SIZE as i64 - 1;
+        (0..CHUNK_SIZE as i64).all(|a| {
+            (0..CHUNK_SIZE as i64).all(|b| {
+                let pos = match face {
+                    BlockFace::XP => Pos::from_xyz(max, a, b),
+                    BlockFace::XN => Pos::from_xyz(0, a, b),
+                    BlockFace::YP => Pos::from_xyz(a, max, b),
+                    BlockFace::YN => Pos::from_xyz(a, 0, b),
+                    BlockFace::ZP => Pos::from_xyz(a, b, max),
+                    BlockFace::ZN => Pos::from_xyz(a, b, 0),
+                };
+                match self.blocks[pos].get_block_type(id_mapping) {
+                    Some(v) => v.visibility() == BlockVisibility::Empty,
+                    None => true,
+                }
+            })
+        })
+    }
+
     /// get the block in the chunk
     pub fn get_pos_in_chunk(&self, pos_in_chunk: Pos) -> Option<BlockId> {
         if pos_in_chunk.all_in_range(0..CHUNK_SIZE as i64) {
-            Some({
-                self.blocks[pos_in_chunk.x() as usize][pos_in_chunk.y() as usize]
-                    [pos_in_chunk.z() as usize]
-            })
+            Some(self.blocks[pos_in_chunk])
         } else {
             //return Some(1_u64.into());
             None
         }
     }
 
+    /// set a block and mark the chunk dirty if it changed
+    ///
+    /// returns the previous block, or `None` if the position is outside the chunk
+    pub fn set_block(&mut self, pos_in_chunk: Pos, id: BlockId) -> Option<BlockId> {
+        let old = self.get_pos_in_chunk(pos_in_chunk)?;
+        if old != id {
+            self.blocks.set(pos_in_chunk, id);
+            self.class = ChunkClass::Mixed;
+            self.modified = true;
+            self.mark_dirty();
+        }
+        Some(old)
+    }
+
+    /// drop the quad group and mesh so they are rebuilt and uploaded again
+    ///
+    /// a chunk entity that was already meshed is moved to `ChunkState::Dirty` by `mark_edited_chunks`
+    pub fn mark_dirty(&mut self) {
+        self.version += 1;
+        self.quad_group = None;
+        self.mesh = None;
+        self.transparent_mesh = None;
+        self.cutout_mesh = None;
+    }
+
+    /// drop only the meshes, for changes like light that keep the quad group
+    pub fn mark_mesh_dirty(&mut self) {
+        self.version += 1;
+        self.mesh = None;
+        self.transparent_mesh = None;
+        self.cutout_mesh = None;
+    }
+
+    /// the faces of the chunk a position lies on, a neighbour chunk on these faces can see the block
+    pub fn border_faces(pos_in_chunk: Pos) -> impl Iterator<Item = BlockFace> {
+        let max = CHUNK_SIZE as i64 - 1;
+        BlockFace::iter_all().filter(move |face| match face {
+            BlockFace::XP => pos_in_chunk.x() == max,
+            BlockFace::XN => pos_in_chunk.x() == 0,
+            BlockFace::YP => pos_in_chunk.y() == max,
+            BlockFace::YN => pos_in_chunk.y() == 0,
+            BlockFace::ZP => pos_in_chunk.z() == max,
+            BlockFace::ZN => pos_in_chunk.z() == 0,
+        })
+    }
+
+    /// the base position of the chunk that contains a world position
+    pub fn base_pos_of(world_pos: Pos) -> Pos {
+        world_pos - Chunk::pos_in_chunk_of(world_pos)
+    }
+
+    /// the position inside its chunk of a world position
+    pub fn pos_in_chunk_of(world_pos: Pos) -> Pos {
+        Pos::from_xyz(
+            world_pos.x().rem_euclid(CHUNK_SIZE as i64),
+            world_pos.y().rem_euclid(CHUNK_SIZE as i64),
+            world_pos.z().rem_euclid(CHUNK_SIZE as i64),
+        )
+    }
+
     pub fn new_filled_with_id(id: BlockId) -> Chunk {
         Chunk {
-            blocks: { Box::from([[[id; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]) },
+            blocks: ChunkBlocks::Single(id),
             ..Default::default()
         }
     }
 }
+
+#[test]
+fn test_world_pos_to_chunk() {
+    let p = Pos::from_xyz(-1, 16, -17);
+    assert_eq!(Chunk::base_pos_of(p), Pos::from_xyz(-16, 16, -32));
+    assert_eq!(Chunk::pos_in_chunk_of(p), Pos::from_xyz(15, 0, 15));
+    assert_eq!(Chunk::base_pos_of(Pos::from_xyz(0, 15, 3)), Pos::default());
+}
+
+#[test]
+fn test_set_block() {
+    use crate::chunk::blocks::IdMapping;
+
+    let mut c = Chunk::default();
+    c.generate_quad_group(&IdMapping::default());
+    assert!(c.get_quad_group().unwrap().iter().next().is_none());
+
+    assert_eq!(
+        c.set_block(Pos::from_xyz(0, 0, 0), 0_u64.into()),
+        Some(0_u64.into())
+    );
+    assert!(c.quad_group.is_some());
+    assert_eq!(
+        c.set_block(Pos::from_xyz(0, 0, 0), 1_u64.into()),
+        Some(0_u64.into())
+    );
+    assert!(c.quad_group.is_none());
+    assert!(c.mesh.is_none());
+    assert_eq!(c.set_block(Pos::from_xyz(0, 0, 16), 1_u64.into()), None);
+
+    assert_eq!(
+        Chunk::border_faces(Pos::from_xyz(0, 15, 4)).collect::<Vec<_>>(),
+        vec![BlockFace::XN, BlockFace::YP]
+    );
+}
+
+#[test]
+fn test_classify() {
+    let id_mapping = IdMapping::default();
+    let mut c = Chunk::default();
+    c.classify(&id_mapping);
+    assert_eq!(c.class, ChunkClass::Empty);
+
+    let mut c = Chunk::new_filled_with_id(1_u64.into());
+    c.classify(&id_mapping);
+    assert_eq!(c.class, ChunkClass::Solid);
+    let solid = c.clone();
+    let neighbours = ChunkNeighbours::from_fn(|_| Some(&solid));
+    assert!(c.is_occluded(&neighbours));
+    assert!(!c.is_occluded(&ChunkNeighbours::default()));
+
+    c.set_block(Pos::from_xyz(3, 3, 3), 0_u64.into());
+    assert_eq!(c.class, ChunkClass::Mixed);
+    c.classify(&id_mapping);
+    assert_eq!(c.class, ChunkClass::Mixed);
+    assert!(!c.is_occluded(&neighbours));
+}
+
+#[test]
+fn test_face_is_empty() {
+    let id_mapping = IdMapping::default();
+    let mut c = Chunk::default();
+    c.classify(&id_mapping);
+    assert!(BlockFace::iter_all().all(|f| c.face_is_empty(f, &id_mapping)));
+
+    c.set_block(Pos::from_xyz(15, 3, 4), 1_u64.into());
+    assert!(!c.face_is_empty(BlockFace::XP, &id_mapping));
+    assert!(c.face_is_empty(BlockFace::XN, &id_mapping));
+    assert!(c.face_is_empty(BlockFace::YP, &id_mapping));
+
+    let solid = Chunk::new_filled_with_id(1_u64.into());
+    assert!(!solid.face_is_empty(BlockFace::ZN, &id_mapping));
+}
@@ -0,0 +1,209 @@
+use crate::chunk::{blocks::BlockId, BlockFace, Pos};
+
+use super::{Chunk, ChunkClass, CHUNK_SIZE};
+
+/// the blocks around a chunk that quad generation looks at
+pub trait NeighbourBlocks {
+    /// get a block just outside the chunk
+    ///
+    /// `pos` is relative to the chunk these are the neighbours of
+    /// and must be outside it along exactly one axis
+    fn get_block(&self, pos: Pos) -> Option<BlockId>;
+
+    /// if the neighbour on this face is loaded and `ChunkClass::Solid`
+    fn is_solid(&self, face: BlockFace) -> bool;
+
+    /// the (sky, block) light just outside the chunk, `None` if the neighbour is not loaded or not lit
+    fn get_light(&self, _pos: Pos) -> Option<(u8, u8)> {
+        None
+    }
+}
+
+/// the loaded chunks next to a chunk, indexed by `BlockFace::to_index`
+///
+/// `None` means the neighbour is not loaded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChunkNeighbours<'a> {
+    pub chunks: [Option<&'a Chunk>; 6],
+}
+
+type BorderLight = Box<[[(u8, u8); CHUNK_SIZE]; CHUNK_SIZE]>;
+
+/// the layers of the neighbours touching a chunk, owned so it can be sent to another thread
+#[derive(Debug, Clone, Default)]
+pub struct NeighbourBorders {
+    /// indexed by `BlockFace::to_index`, then by the two coordinates that are not the face normal
+    slices: [Option<Box<[[BlockId; CHUNK_SIZE]; CHUNK_SIZE]>>; 6],
+    /// the (sky, block) light of the same layers, for the lit neighbours
+    light: [Option<BorderLight>; 6],
+    solid: [bool; 6],
+}
+
+/// the face of the chunk `pos` is outside of, and the position in the chunk on that side
+fn outside_face(pos: Pos) -> Option<(BlockFace, Pos)> {
+    let size = CHUNK_SIZE as i64;
+    let face = if pos.x() >= size {
+        BlockFace::XP
+    } else if pos.x() < 0 {
+        BlockFace::XN
+    } else if pos.y() >= size {
+        BlockFace::YP
+    } else if pos.y() < 0 {
+        BlockFace::YN
+    } else if pos.z() >= size {
+        BlockFace::ZP
+    } else if pos.z() < 0 {
+        BlockFace::ZN
+    } else {
+        return None;
+    };
+    Some((face, Chunk::pos_in_chunk_of(pos)))
+}
+
+/// the coordinates of a position in the slice for this face
+fn slice_index(face: BlockFace, pos: Pos) -> (usize, usize) {
+    let (a, b) = match face {
+        BlockFace::XP | BlockFace::XN => (pos.y(), pos.z()),
+        BlockFace::YP | BlockFace::YN => (pos.x(), pos.z()),
+        BlockFace::ZP | BlockFace::ZN => (pos.x(), pos.y()),
+    };
+    (a as usize, b as usize)
+}
+
+impl<'a> ChunkNeighbours<'a> {
+    pub fn from_fn<F>(mut f: F) -> ChunkNeighbours<'a>
+    where
+        F: FnMut(BlockFace) -> Option<&'a Chunk>,
+    {
+        let mut chunks = [None; 6];
+        for face in BlockFace::iter_all() {
+            chunks[face.to_index()] = f(face);
+        }
+        ChunkNeighbours { chunks }
+    }
+
+    pub fn get(&self, face: BlockFace) -> Option<&'a Chunk> {
+        self.chunks[face.to_index()]
+    }
+
+    /// get a block just outside the chunk, see `NeighbourBlocks::get_block`
+    pub fn get_block(&self, pos: Pos) -> Option<BlockId> {
+        let (face, pos) = outside_face(pos)?;
+        self.get(face)?.get_pos_in_chunk(pos)
+    }
+}
+
+impl NeighbourBlocks for ChunkNeighbours<'_> {
+    fn get_block(&self, pos: Pos) -> Option<BlockId> {
+        ChunkNeighbours::get_block(self, pos)
+    }
+
+    fn is_solid(&self, face: BlockFace) -> bool {
+        matches!(self.get(face), Some(c) if c.class == ChunkClass::Solid)
+    }
+
+    fn get_light(&self, pos: Pos) -> Option<(u8, u8)> {
+        let (face, pos) = outside_face(pos)?;
+        Some(self.get(face)?.light.as_ref()?.get(pos))
+    }
+}
+
+impl NeighbourBorders {
+    /// copy the layer of every loaded neighbour that touches the chunk
+    pub fn from_neighbours(neighbours: &ChunkNeighbours) -> NeighbourBorders {
+        let mut borders = NeighbourBorders::default();
+        let max = CHUNK_SIZE as i64 - 1;
+        for face in BlockFace::iter_all() {
+            let chunk = match neighbours.get(face) {
+                Some(c) => c,
+                None => continue,
+            };
+            let mut slice = Box::new([[BlockId::default(); CHUNK_SIZE]; CHUNK_SIZE]);
+            let mut light = chunk
+                .light
+                .as_ref()
+                .map(|_| Box::new([[(0, 0); CHUNK_SIZE]; CHUNK_SIZE]));
+            for a in 0..CHUNK_SIZE as i64 {
+                for b in 0..CHUNK_SIZE as i64 {
+                    // the layer of the neighbour on the side facing this chunk
+                    let pos = match face {
+                        BlockFace::XP => Pos::from_xyz(0, a, b),
+                        BlockFace::XN => Pos::from_xyz(max, a, b),
+                        BlockFace::YP => Pos::from_xyz(a, 0, b),
+                        BlockFace::YN => Pos::from_xyz(a, max, b),
+                        BlockFace::ZP => Pos::from_xyz(a, b, 0),
+                        BlockFace::ZN => Pos::from_xyz(a, b, max),
+                    };
+                    let (i, j) = slice_index(face, pos);
+                    slice[i][j] = chunk.blocks[pos];
+                    if let (Some(l), Some(chunk_light)) = (&mut light, &chunk.light) {
+                        l[i][j] = chunk_light.get(pos);
+                    }
+                }
+            }
+            borders.slices[face.to_index()] = Some(slice);
+            borders.light[face.to_index()] = light;
+            borders.solid[face.to_index()] = chunk.class == ChunkClass::Solid;
+        }
+        borders
+    }
+}
+
+impl NeighbourBlocks for NeighbourBorders {
+    fn get_block(&self, pos: Pos) -> Option<BlockId> {
+        let (face, pos) = outside_face(pos)?;
+        let (i, j) = slice_index(face, pos);
+        self.slices[face.to_index()].as_ref().map(|s| s[i][j])
+    }
+
+    fn is_solid(&self, face: BlockFace) -> bool {
+        self.solid[face.to_index()]
+    }
+
+    fn get_light(&self, pos: Pos) -> Option<(u8, u8)> {
+        let (face, pos) = outside_face(pos)?;
+        let (i, j) = slice_index(face, pos);
+        self.light[face.to_index()].as_ref().map(|s| s[i][j])
+    }
+}
+
+#[test]
+fn test_borders_match_neighbours() {
+    use crate::chunk::blocks::IdMapping;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let id_mapping = IdMapping::default();
+    let mut r = StdRng::seed_from_u64(7);
+    let mut chunks = Vec::new();
+    for _ in 0..7 {
+        let mut c = Chunk::default();
+        for (pos, _) in Chunk::default().iter() {
+            c.blocks.set(pos, r.gen_range(0..2_u64).into());
+        }
+        chunks.push(c);
+    }
+    let (center, around) = chunks.split_first().unwrap();
+    let neighbours = ChunkNeighbours::from_fn(|face| {
+        if face == BlockFace::ZN {
+            None
+        } else {
+            Some(&around[face.to_index()])
+        }
+    });
+    let borders = NeighbourBorders::from_neighbours(&neighbours);
+
+    for face in BlockFace::iter_all() {
+        for p in Pos::from_xyz(-1, -1, -1).iter_cube(17, 17, 17) {
+            if outside_face(p).map(|(f, _)| f) == Some(face) {
+                assert_eq!(
+                    NeighbourBlocks::get_block(&borders, p),
+                    neighbours.get_block(p)
+                );
+            }
+        }
+    }
+    assert_eq!(
+        center.build_quad_group(&id_mapping, &neighbours),
+        center.build_quad_group(&id_mapping, &borders)
+    );
+}
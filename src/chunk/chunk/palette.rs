@@ -0,0 +1,434 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::chunk::{blocks::BlockId, Pos};
+
+use super::CHUNK_SIZE;
+
+const BLOCKS_IN_CHUNK: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// the blocks of a chunk
+///
+/// a uniform chunk stores one id, any other chunk stores a palette of the ids it uses
+/// and a packed index into the palette for every block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkBlocks {
+    Single(BlockId),
+    Palette(PalettedBlocks),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalettedBlocks {
+    palette: Vec<BlockId>,
+    /// bits per index, indices never cross two words
+    bits: u32,
+    data: Vec<u64>,
+}
+
+impl Default for ChunkBlocks {
+    fn default() -> Self {
+        ChunkBlocks::Single(BlockId::default())
+    }
+}
+
+/// x changes fastest, the same order as `ChunkIter`
+pub(crate) fn index_of(pos: Pos) -> usize {
+    assert!(pos.all_in_range(0..CHUNK_SIZE as i64));
+    pos.x() as usize + pos.y() as usize * CHUNK_SIZE + pos.z() as usize * CHUNK_SIZE * CHUNK_SIZE
+}
+
+/// the inverse of `index_of`
+pub(crate) fn pos_of_index(i: usize) -> Pos {
+    Pos::from_xyz(
+        (i % CHUNK_SIZE) as i64,
+        (i / CHUNK_SIZE % CHUNK_SIZE) as i64,
+        (i / (CHUNK_SIZE * CHUNK_SIZE)) as i64,
+    )
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut v = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let (b, rest) = data.split_first()?;
+        *data = rest;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+fn bits_for(palette_len: usize) -> u32 {
+    (usize::BITS - (palette_len.max(2) - 1).leading_zeros()).max(1)
+}
+
+impl PalettedBlocks {
+    fn with_bits(palette: Vec<BlockId>, bits: u32) -> PalettedBlocks {
+        let per_word = (64 / bits) as usize;
+        PalettedBlocks {
+            palette,
+            bits,
+            data: vec![0; (BLOCKS_IN_CHUNK + per_word - 1) / per_word],
+        }
+    }
+
+    fn get_index(&self, i: usize) -> usize {
+        let per_word = (64 / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        ((self.data[i / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_index(&mut self, i: usize, v: usize) {
+        let per_word = (64 / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = ((1_u64 << self.bits) - 1) << shift;
+        let word = &mut self.data[i / per_word];
+        *word = (*word & !mask) | ((v as u64) << shift);
+    }
+
+    fn get(&self, i: usize) -> &BlockId {
+        &self.palette[self.get_index(i)]
+    }
+
+    /// rebuild with only the used palette entries and the fewest bits
+    /// that leave room for `reserve` more entries
+    fn compacted(&self, reserve: usize) -> PalettedBlocks {
+        let mut palette = Vec::new();
+        let mut remap = vec![usize::MAX; self.palette.len()];
+        for i in 0..BLOCKS_IN_CHUNK {
+            let old = self.get_index(i);
+            if remap[old] == usize::MAX {
+                remap[old] = palette.len();
+                palette.push(self.palette[old]);
+            }
+        }
+        let bits = bits_for(palette.len() + reserve);
+        let mut out = PalettedBlocks::with_bits(palette, bits);
+        for i in 0..BLOCKS_IN_CHUNK {
+            out.set_index(i, remap[self.get_index(i)]);
+        }
+        out
+    }
+}
+
+impl ChunkBlocks {
+    pub fn get(&self, pos: Pos) -> BlockId {
+        self[pos]
+    }
+
+    pub fn set(&mut self, pos: Pos, id: BlockId) {
+        let i = index_of(pos);
+        let blocks = match self {
+            ChunkBlocks::Single(v) if *v == id => return,
+            ChunkBlocks::Single(v) => {
+                *self = ChunkBlocks::Palette(PalettedBlocks::with_bits(vec![*v], 1));
+                match self {
+                    ChunkBlocks::Palette(p) => p,
+                    ChunkBlocks::Single(_) => unreachable!(),
+                }
+            }
+            ChunkBlocks::Palette(p) => p,
+        };
+
+        let palette_index = match blocks.palette.iter().position(|v| *v == id) {
+            Some(v) => v,
+            None => {
+                if blocks.palette.len() == 1 << blocks.bits {
+                    // the palette only grows on set, drop the unused entries before growing
+                    *blocks = blocks.compacted(1);
+                }
+                blocks.palette.push(id);
+                blocks.palette.len() - 1
+            }
+        };
+        blocks.set_index(i, palette_index);
+    }
+
+    /// turn back into a `Single` if only one id is left and drop unused palette entries
+    pub fn compact(&mut self) {
+        if let ChunkBlocks::Palette(p) = self {
+            let p = p.compacted(0);
+            *self = if p.palette.len() == 1 {
+                ChunkBlocks::Single(p.palette[0])
+            } else {
+                ChunkBlocks::Palette(p)
+            };
+        }
+    }
+
+    /// replace every id with `f(id)`, ids that become the same are merged
+    pub fn map_ids<F: FnMut(BlockId) -> BlockId>(&mut self, mut f: F) {
+        match self {
+            ChunkBlocks::Single(v) => *v = f(*v),
+            ChunkBlocks::Palette(p) => {
+                for v in p.palette.iter_mut() {
+                    *v = f(*v);
+                }
+                let merged = p
+                    .palette
+                    .iter()
+                    .enumerate()
+                    .any(|(i, v)| p.palette[..i].contains(v));
+                if merged {
+                    *self = ChunkBlocks::from(self.to_array());
+                }
+            }
+        }
+    }
+
+    /// the only id in the chunk, if it is uniform
+    pub fn single(&self) -> Option<BlockId> {
+        match self {
+            ChunkBlocks::Single(v) => Some(*v),
+            ChunkBlocks::Palette(_) => None,
+        }
+    }
+
+    /// the ids that may be in the chunk, unused ids stay in the palette until `compact`
+    pub fn palette(&self) -> &[BlockId] {
+        match self {
+            ChunkBlocks::Single(v) => std::slice::from_ref(v),
+            ChunkBlocks::Palette(p) => &p.palette,
+        }
+    }
+
+    /// bytes allocated on the heap
+    pub fn heap_size(&self) -> usize {
+        match self {
+            ChunkBlocks::Single(_) => 0,
+            ChunkBlocks::Palette(p) => {
+                p.palette.capacity() * std::mem::size_of::<BlockId>()
+                    + p.data.capacity() * std::mem::size_of::<u64>()
+            }
+        }
+    }
+
+    /// runs of equal ids in `ChunkIter` order, every run is its length then the id, as LEB128 varints
+    pub fn to_rle(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut run: Option<(BlockId, u64)> = None;
+        for i in 0..BLOCKS_IN_CHUNK {
+            let id = self[pos_of_index(i)];
+            run = match run {
+                Some((v, n)) if v == id => Some((v, n + 1)),
+                Some((v, n)) => {
+                    write_varint(&mut out, n);
+                    write_varint(&mut out, v.into());
+                    Some((id, 1))
+                }
+                None => Some((id, 1)),
+            };
+        }
+        if let Some((v, n)) = run {
+            write_varint(&mut out, n);
+            write_varint(&mut out, v.into());
+        }
+        out
+    }
+
+    /// `None` if the runs do not cover the chunk exactly
+    pub fn from_rle(mut data: &[u8]) -> Option<ChunkBlocks> {
+        let mut runs = Vec::new();
+        let mut total = 0_usize;
+        while !data.is_empty() {
+            let n = read_varint(&mut data)? as usize;
+            let id = BlockId::from(read_varint(&mut data)?);
+            total = total.checked_add(n)?;
+            runs.push((n, id));
+        }
+        if total != BLOCKS_IN_CHUNK {
+            return None;
+        }
+        if let [(_, id)] = runs[..] {
+            return Some(ChunkBlocks::Single(id));
+        }
+
+        let mut b = Box::new([[[BlockId::default(); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        let mut i = 0;
+        for (n, id) in runs {
+            for _ in 0..n {
+                b[pos_of_index(i)] = id;
+                i += 1;
+            }
+        }
+        Some(b.into())
+    }
+
+    pub fn to_array(&self) -> Box<[[[BlockId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]> {
+        let mut b = Box::new([[[BlockId::default(); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        for p in Pos::default().iter_cube(
+            CHUNK_SIZE as i64 - 1,
+            CHUNK_SIZE as i64 - 1,
+            CHUNK_SIZE as i64 - 1,
+        ) {
+            b[p] = self[p];
+        }
+        b
+    }
+}
+
+impl std::ops::Index<Pos> for ChunkBlocks {
+    type Output = BlockId;
+
+    fn index(&self, index: Pos) -> &Self::Output {
+        match self {
+            ChunkBlocks::Single(v) => {
+                assert!(index.all_in_range(0..CHUNK_SIZE as i64));
+                v
+            }
+            ChunkBlocks::Palette(p) => p.get(index_of(index)),
+        }
+    }
+}
+
+/// as the bytes of `ChunkBlocks::to_rle`
+impl Serialize for ChunkBlocks {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.to_rle())
+    }
+}
+
+struct RleVisitor;
+
+impl<'de> de::Visitor<'de> for RleVisitor {
+    type Value = ChunkBlocks;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("run-length encoded chunk blocks")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ChunkBlocks, E> {
+        ChunkBlocks::from_rle(v).ok_or_else(|| E::custom("the runs do not cover the chunk"))
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<ChunkBlocks, A::Error> {
+        let mut v = Vec::new();
+        while let Some(b) = seq.next_element::<u8>()? {
+            v.push(b);
+        }
+        self.visit_bytes(&v)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChunkBlocks {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_bytes(RleVisitor)
+    }
+}
+
+impl From<Box<[[[BlockId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]>> for ChunkBlocks {
+    fn from(value: Box<[[[BlockId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]>) -> Self {
+        let mut palette = Vec::new();
+        for p in Pos::default().iter_cube(
+            CHUNK_SIZE as i64 - 1,
+            CHUNK_SIZE as i64 - 1,
+            CHUNK_SIZE as i64 - 1,
+        ) {
+            if !palette.contains(&value[p]) {
+                palette.push(value[p]);
+            }
+        }
+        if palette.len() == 1 {
+            return ChunkBlocks::Single(palette[0]);
+        }
+
+        let bits = bits_for(palette.len());
+        let mut blocks = PalettedBlocks::with_bits(palette, bits);
+        for p in Pos::default().iter_cube(
+            CHUNK_SIZE as i64 - 1,
+            CHUNK_SIZE as i64 - 1,
+            CHUNK_SIZE as i64 - 1,
+        ) {
+            let v = blocks.palette.iter().position(|v| *v == value[p]).unwrap();
+            blocks.set_index(index_of(p), v);
+        }
+        ChunkBlocks::Palette(blocks)
+    }
+}
+
+#[test]
+fn test_palette() {
+    let mut b = ChunkBlocks::default();
+    assert_eq!(b.heap_size(), 0);
+
+    let mut expected = Box::new([[[BlockId::default(); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+    let mut id: u64 = 0;
+    for p in Pos::default().iter_cube(15, 15, 15) {
+        b.set(p, (id % 300).into());
+        expected[p] = (id % 300).into();
+        id += 7;
+    }
+    assert_eq!(b.to_array(), expected);
+    assert_eq!(ChunkBlocks::from(expected.clone()), {
+        let mut c = b.clone();
+        c.compact();
+        c
+    });
+
+    for p in Pos::default().iter_cube(15, 15, 15) {
+        b.set(p, 5_u64.into());
+    }
+    assert_eq!(b[Pos::from_xyz(3, 4, 5)], 5_u64.into());
+    b.compact();
+    assert_eq!(b, ChunkBlocks::Single(5_u64.into()));
+}
+
+#[test]
+fn test_palette_memory() {
+    let mut b = ChunkBlocks::default();
+    b.set(Pos::from_xyz(1, 2, 3), 1_u64.into());
+    assert_eq!(b.palette().len(), 2);
+    assert!(b.heap_size() < 1024);
+    assert_eq!(b[Pos::from_xyz(1, 2, 3)], 1_u64.into());
+    assert_eq!(b[Pos::from_xyz(3, 2, 1)], 0_u64.into());
+}
+
+#[test]
+fn test_rle() {
+    let b = ChunkBlocks::Single(300_u64.into());
+    assert_eq!(b.to_rle(), vec![0x80, 0x20, 0xac, 0x02]);
+    assert_eq!(ChunkBlocks::from_rle(&b.to_rle()), Some(b));
+
+    let mut b = ChunkBlocks::default();
+    for i in (0..BLOCKS_IN_CHUNK).step_by(5) {
+        b.set(pos_of_index(i), (i as u64 % 3).into());
+    }
+    b.set(Pos::from_xyz(15, 15, 15), 1000_u64.into());
+    let rle = b.to_rle();
+    assert_eq!(
+        ChunkBlocks::from_rle(&rle).unwrap().to_array(),
+        b.to_array()
+    );
+
+    let json = serde_json::to_string(&b).unwrap();
+    let from_json = serde_json::from_str::<ChunkBlocks>(&json).unwrap();
+    assert_eq!(from_json.to_array(), b.to_array());
+
+    assert_eq!(ChunkBlocks::from_rle(&rle[..rle.len() - 2]), None);
+    assert_eq!(ChunkBlocks::from_rle(&[0x80]), None);
+    assert_eq!(ChunkBlocks::from_rle(&[]), None);
+}
+
+#[test]
+fn test_map_ids() {
+    let mut b = ChunkBlocks::default();
+    b.set(Pos::from_xyz(1, 0, 0), 1_u64.into());
+    b.set(Pos::from_xyz(2, 0, 0), 2_u64.into());
+    b.map_ids(|id| (u64::from(id) * 10).into());
+    assert_eq!(b[Pos::from_xyz(2, 0, 0)], 20_u64.into());
+    assert_eq!(b.palette().len(), 3);
+
+    b.map_ids(|id| if id == 0_u64.into() { id } else { 5_u64.into() });
+    assert_eq!(b.palette().len(), 2);
+    assert_eq!(b[Pos::from_xyz(1, 0, 0)], 5_u64.into());
+    assert_eq!(b[Pos::from_xyz(2, 0, 0)], 5_u64.into());
+    assert_eq!(b[Pos::from_xyz(3, 0, 0)], 0_u64.into());
+}
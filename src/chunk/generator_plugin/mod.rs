@@ -3,16 +3,119 @@ pub use systems::*;
 
 mod systems;
 
-#[derive(Debug, Clone, Copy, Component, PartialEq, Eq, Hash)]
-pub struct ChunkGeneratorPlugin;
+use crate::chunk::{
+    blocks::BlockRegistry,
+    chunk::{simple_generator::SimpleGenerator, Chunk, ChunkGenerator, CHUNK_SIZE},
+    Pos,
+};
+
+/// loads and unloads chunks around the cameras using `generator`
+///
+/// chunks in a `WorldSave` resource are loaded instead of generated,
+/// a `SaveWorld` event writes the modified chunks into it
+#[derive(Debug, Clone)]
+pub struct ChunkGeneratorPlugin {
+    pub info: GeneratorInfo,
+    pub generator: WorldGenerator,
+}
 
+/// keeps the chunks around its entity loaded, ranges are in chunks
+///
+/// a camera without a loader uses the ranges in `GeneratorInfo`
 #[derive(Debug, Clone, Copy, Component, PartialEq, Eq, Hash)]
-pub struct Player;
+pub struct ChunkLoader {
+    pub range_xz: u64,
+    pub range_yp: u64,
+    pub range_yn: u64,
+    pub range_r: u64,
+}
+
+impl ChunkLoader {
+    pub fn from_info(info: &GeneratorInfo) -> ChunkLoader {
+        ChunkLoader {
+            range_xz: info.range_xz,
+            range_yp: info.range_yp,
+            range_yn: info.range_yn,
+            range_r: info.range_r,
+        }
+    }
+
+    /// the offsets, in chunks, of every chunk this loader keeps
+    pub fn offsets(self) -> impl Iterator<Item = Pos> {
+        let r1 = Pos::from_xyz(
+            self.range_xz as i64,
+            self.range_yp as i64,
+            self.range_xz as i64,
+        );
+        let r2 = -Pos::from_xyz(
+            self.range_xz as i64,
+            self.range_yn as i64,
+            self.range_xz as i64,
+        );
+        Pos::iter_range(r1, r2).filter(move |p| {
+            p.x() * p.x() + p.y() * p.y() + p.z() * p.z() < (self.range_r * self.range_r) as i64
+        })
+    }
+
+    /// the same loader with every range grown by `margin`
+    pub fn with_margin(self, margin: u64) -> ChunkLoader {
+        ChunkLoader {
+            range_xz: self.range_xz + margin,
+            range_yp: self.range_yp + margin,
+            range_yn: self.range_yn + margin,
+            range_r: self.range_r + margin,
+        }
+    }
+
+    /// the base position of the chunk the loader is in
+    pub fn center(transform: &Transform) -> Pos {
+        Chunk::base_pos_of(Pos::from_xyz(
+            transform.translation.x as i64,
+            transform.translation.y as i64,
+            transform.translation.z as i64,
+        ))
+    }
+
+    /// the base positions of every chunk this loader keeps
+    pub fn chunks_around(self, transform: &Transform) -> impl Iterator<Item = Pos> {
+        let center_pos = ChunkLoader::center(transform);
+        self.offsets()
+            .map(move |i| center_pos + i * CHUNK_SIZE as i64)
+    }
+}
+
+impl ChunkGeneratorPlugin {
+    pub fn new<T>(info: GeneratorInfo, generator: T) -> ChunkGeneratorPlugin
+    where
+        T: ChunkGenerator + Send + Sync + 'static,
+    {
+        ChunkGeneratorPlugin {
+            info,
+            generator: WorldGenerator::new(generator),
+        }
+    }
+}
+
+impl Default for ChunkGeneratorPlugin {
+    fn default() -> Self {
+        ChunkGeneratorPlugin::new(GeneratorInfo::default(), SimpleGenerator)
+    }
+}
 
 impl Plugin for ChunkGeneratorPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(new_chunks)
-            .add_startup_system(startup)
-            .add_system(delete_chunks);
+        app.insert_resource(self.info.clone())
+            .insert_resource(self.generator.clone())
+            .init_resource::<AllChunks>()
+            .init_resource::<PendingChunks>()
+            .init_resource::<PendingRegions>()
+            .insert_resource(UnloadedChunks::new(self.info.unload_cache_size))
+            .init_resource::<BlockRegistry>()
+            .add_event::<SaveWorld>()
+            .add_system(finish_regions)
+            .add_system(new_chunks.after(finish_regions))
+            .add_system(delete_chunks)
+            .add_system(finish_chunks.after(delete_chunks))
+            .add_system(save_chunks.after(delete_chunks));
     }
 }
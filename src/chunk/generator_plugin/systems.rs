@@ -1,18 +1,28 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::Mutex,
+    fmt::Debug,
+    io,
+    sync::Arc,
 };
 
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, IoTaskPool, Task},
+};
+use futures_lite::future;
+use serde::{Deserialize, Serialize};
 
 use crate::chunk::{
-    chunk::{simple_generator::SimpleGenerator, Chunk, ChunkGenerator, Seed, CHUNK_SIZE},
+    blocks::BlockRegistry,
+    chunk::{Chunk, ChunkGenerator, Seed, CHUNK_SIZE},
+    plugin::ChunkState,
+    storage::{decompress_chunk, region_of, save_world, Region, WorldSave},
     Pos,
 };
 
-use super::Player;
+use super::ChunkLoader;
 
-#[derive(Debug, Clone, PartialEq, Resource)]
+#[derive(Debug, Clone, PartialEq, Resource, Serialize, Deserialize)]
 pub struct GeneratorInfo {
     pub range_xz: u64,
     pub range_yp: u64,
@@ -21,6 +31,49 @@ pub struct GeneratorInfo {
     pub seed: Seed,
     pub load_how_many_chunks_per_frame: usize,
     pub unload_how_many_chunks_per_frame: usize,
+    /// the half angle in radians of the cone in front of the viewer whose chunks are loaded first,
+    /// 0 ignores the view direction
+    pub view_angle: f32,
+    /// chunks are unloaded this many chunks further out than they are loaded
+    pub unload_margin: u64,
+    /// how many unmodified unloaded chunks are kept to be loaded again without generating
+    pub unload_cache_size: usize,
+}
+
+impl Default for GeneratorInfo {
+    fn default() -> Self {
+        Self {
+            range_xz: 16,
+            range_yp: 3,
+            range_yn: 2,
+            range_r: 16,
+            seed: Seed { seed: 3 },
+            load_how_many_chunks_per_frame: 50,
+            unload_how_many_chunks_per_frame: 50,
+            view_angle: std::f32::consts::FRAC_PI_3,
+            unload_margin: 2,
+            unload_cache_size: 256,
+        }
+    }
+}
+
+/// the generator new chunks come from
+#[derive(Clone, Resource)]
+pub struct WorldGenerator(pub Arc<dyn ChunkGenerator + Send + Sync>);
+
+impl WorldGenerator {
+    pub fn new<T>(generator: T) -> WorldGenerator
+    where
+        T: ChunkGenerator + Send + Sync + 'static,
+    {
+        WorldGenerator(Arc::new(generator))
+    }
+}
+
+impl Debug for WorldGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("WorldGenerator").finish()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Default, Resource)]
@@ -28,139 +81,442 @@ pub struct AllChunks {
     chunks: HashMap<Pos, Entity>,
 }
 
-pub fn startup(mut commands: Commands) {
-    commands.insert_resource(GeneratorInfo {
-        range_xz: 16,
-        range_yp: 3,
-        range_yn: 2,
-        range_r: 16,
-        seed: Seed { seed: 3 },
-        load_how_many_chunks_per_frame: 50,
-        unload_how_many_chunks_per_frame: 50,
-    });
-    commands.insert_resource(AllChunks::default());
+/// chunks being generated on the `AsyncComputeTaskPool`, with the `ChunkState::Generating` entity
+/// reserved for them
+///
+/// dropping a task cancels it
+#[derive(Debug, Default, Resource)]
+pub struct PendingChunks {
+    tasks: HashMap<Pos, (Entity, Task<Chunk>)>,
+}
+
+impl PendingChunks {
+    pub fn contains(&self, base_pos_of_chunk: Pos) -> bool {
+        self.tasks.contains_key(&base_pos_of_chunk)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+/// regions of the `WorldSave` being read on the `IoTaskPool`, `new_chunks` waits for them
+///
+/// the chunks of a region that could not be read are generated
+#[derive(Debug, Default, Resource)]
+pub struct PendingRegions {
+    tasks: HashMap<Pos, Task<io::Result<Region>>>,
+    failed: HashSet<Pos>,
+}
+
+impl PendingRegions {
+    /// the saved chunk at `base_pos_of_chunk` can be loaded without reading from disk,
+    /// starts reading its region if not
+    pub fn ready(&mut self, save: &WorldSave, base_pos_of_chunk: Pos) -> bool {
+        let region = region_of(base_pos_of_chunk);
+        if save.has_region(region) || self.failed.contains(&region) {
+            return true;
+        }
+        self.tasks.entry(region).or_insert_with(|| {
+            let reader = save.region_reader(region);
+            IoTaskPool::get().spawn(async move { reader.read() })
+        });
+        false
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+/// recently unloaded chunks, loaded again instead of generated
+///
+/// unmodified chunks are dropped least recently unloaded first once there are more than `capacity`,
+/// modified chunks are never dropped
+#[derive(Debug, Default, Resource)]
+pub struct UnloadedChunks {
+    chunks: HashMap<Pos, (u64, Chunk)>,
+    capacity: usize,
+    counter: u64,
+}
+
+impl UnloadedChunks {
+    pub fn new(capacity: usize) -> UnloadedChunks {
+        UnloadedChunks {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn insert(&mut self, mut chunk: Chunk) {
+        chunk.quad_group = None;
+        chunk.mesh = None;
+        chunk.transparent_mesh = None;
+        chunk.cutout_mesh = None;
+        self.counter += 1;
+        self.chunks
+            .insert(chunk.base_pos_of_chunk, (self.counter, chunk));
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        let mut unmodified = self.chunks.values().filter(|(_, c)| !c.modified).count();
+        while unmodified > self.capacity {
+            let oldest = self
+                .chunks
+                .iter()
+                .filter(|(_, (_, c))| !c.modified)
+                .min_by_key(|(_, (t, _))| *t)
+                .map(|(p, _)| *p);
+            if let Some(p) = oldest {
+                self.chunks.remove(&p);
+            }
+            unmodified -= 1;
+        }
+    }
+
+    pub fn take(&mut self, base_pos_of_chunk: Pos) -> Option<Chunk> {
+        self.chunks.remove(&base_pos_of_chunk).map(|(_, c)| c)
+    }
+
+    pub fn contains(&self, base_pos_of_chunk: Pos) -> bool {
+        self.chunks.contains_key(&base_pos_of_chunk)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// the unloaded chunks with unsaved edits
+    pub fn modified(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.values().map(|(_, c)| c).filter(|c| c.modified)
+    }
+
+    /// the modified chunks were saved, they can be dropped like the others
+    pub fn mark_saved(&mut self) {
+        for (_, c) in self.chunks.values_mut() {
+            c.modified = false;
+        }
+        self.evict();
+    }
+}
+
+/// write the modified loaded and unloaded chunks into `WorldSave` and to disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SaveWorld;
+
+/// spawn an entity for the chunk at `base`, the `Chunk` is inserted once it is generated
+fn spawn_chunk(commands: &mut Commands, base: Pos) -> Entity {
+    commands
+        .spawn(ChunkState::Generating)
+        .insert(TransformBundle::from_transform(
+            Transform::from_translation(Vec3 {
+                x: base.x() as f32,
+                y: base.y() as f32,
+                z: base.z() as f32,
+            }),
+        ))
+        .id()
+}
+
+/// sort the base positions of chunks so the chunks in view come first, then the closest ones
+///
+/// a chunk is in view if it reaches into the cone of `view_angle` around `forward` from `eye`
+pub fn load_order<I>(bases: I, eye: Vec3, forward: Vec3, view_angle: f32) -> Vec<Pos>
+where
+    I: Iterator<Item = Pos>,
+{
+    let forward = forward.normalize_or_zero();
+    // the chunk reaches this far from its center
+    let radius = (3.0_f32).sqrt() * CHUNK_SIZE as f32 / 2.0;
+    let mut v = bases
+        .map(|base| {
+            let (x, y, z) = base.to_f32_truple();
+            let to_center = Vec3::new(x, y, z) + Vec3::splat(CHUNK_SIZE as f32 / 2.0) - eye;
+            let distance = to_center.length();
+            let in_view = view_angle > 0.0
+                && (distance <= radius
+                    || to_center.angle_between(forward) <= view_angle + (radius / distance).asin());
+            (!in_view, distance, base)
+        })
+        .collect::<Vec<_>>();
+    v.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    v.into_iter().map(|(_, _, p)| p).collect()
 }
 
+/// the loaders, and the cameras without one
+pub type LoaderQuery<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static Transform, Option<&'static ChunkLoader>),
+    Or<(With<Camera>, With<ChunkLoader>)>,
+>;
+
+/// the chunks around a loader in `load_order`, kept until the loader enters another chunk
+///
+/// the chunks before `next` are loaded, a loader keeps the chunks around it so they stay loaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadQueue {
+    center: Pos,
+    loader: ChunkLoader,
+    order: Vec<Pos>,
+    next: usize,
+}
+
+impl LoadQueue {
+    fn new(loader: ChunkLoader, transform: &Transform, view_angle: f32) -> LoadQueue {
+        LoadQueue {
+            center: ChunkLoader::center(transform),
+            loader,
+            order: load_order(
+                loader.chunks_around(transform),
+                transform.translation,
+                transform.forward(),
+                view_angle,
+            ),
+            next: 0,
+        }
+    }
+}
+
+/// start generating the missing chunks around every loader, in `load_order`
+///
+/// the loaders take turns, so each gets its share of `GeneratorInfo::load_how_many_chunks_per_frame`,
+/// recently unloaded chunks are loaded from `UnloadedChunks` instead,
+/// and chunks in the `WorldSave` are decompressed instead of generated,
+/// a chunk whose region is not in memory waits for `PendingRegions` to read it
+#[allow(clippy::too_many_arguments)]
 pub fn new_chunks(
-    playes: Query<&Transform, With<Camera>>,
+    loaders: LoaderQuery,
+    mut queues: Local<HashMap<Entity, LoadQueue>>,
     mut all_chunks: ResMut<AllChunks>,
+    mut pending: ResMut<PendingChunks>,
+    mut regions: ResMut<PendingRegions>,
+    mut unloaded: ResMut<UnloadedChunks>,
+    mut save: Option<ResMut<WorldSave>>,
     generatier_info: Res<GeneratorInfo>,
+    generator: Res<WorldGenerator>,
     mut commands: Commands,
 ) {
-    playes.for_each(|transform| {
-        let center_pos = Pos::from_xyz(
-            transform.translation.x as i64,
-            transform.translation.y as i64,
-            transform.translation.z as i64,
-        );
-
-        let center_pos = Pos::from_xyz(
-            center_pos.x() - (center_pos.x().rem_euclid(CHUNK_SIZE as i64)),
-            center_pos.y() - (center_pos.y().rem_euclid(CHUNK_SIZE as i64)),
-            center_pos.z() - (center_pos.z().rem_euclid(CHUNK_SIZE as i64)),
-        );
+    // every loader, and where it got to in its queue this frame
+    let mut active = Vec::new();
+    for (e, transform, loader) in loaders.iter() {
+        let loader = loader
+            .copied()
+            .unwrap_or_else(|| ChunkLoader::from_info(&generatier_info));
+        let queue = queues
+            .entry(e)
+            .or_insert_with(|| LoadQueue::new(loader, transform, generatier_info.view_angle));
+        if queue.center != ChunkLoader::center(transform) || queue.loader != loader {
+            *queue = LoadQueue::new(loader, transform, generatier_info.view_angle);
+        }
+        while queue.next < queue.order.len()
+            && all_chunks.chunks.contains_key(&queue.order[queue.next])
+        {
+            queue.next += 1;
+        }
+        active.push((e, queue.next));
+    }
+    queues.retain(|e, _| active.iter().any(|(a, _)| a == e));
 
-        let r1 = Pos::from_xyz(
-            generatier_info.range_xz as i64,
-            generatier_info.range_yp as i64,
-            generatier_info.range_xz as i64,
-        );
-        let r2 = -Pos::from_xyz(
-            generatier_info.range_xz as i64,
-            generatier_info.range_yn as i64,
-            generatier_info.range_xz as i64,
-        );
-        let mut uploaded = 0_usize;
-        for i in Pos::iter_range(r1, r2).filter(|p| {
-            p.x() * p.x() + p.y() * p.y() + p.z() * p.z()
-                < (generatier_info.range_r * generatier_info.range_r) as i64
-        }) {
-            //dbg!(i);
-            let base = center_pos + i * CHUNK_SIZE as i64;
-            if !all_chunks.chunks.contains_key(&base) {
-                // dbg!(base == base);
-                // dbg!(base);
-                let e = commands
-                    .spawn(SimpleGenerator.generate_chunk(base, generatier_info.seed))
-                    .insert(TransformBundle::from_transform(
-                        Transform::from_translation(Vec3 {
-                            x: base.x() as f32,
-                            y: base.y() as f32,
-                            z: base.z() as f32,
-                        }),
-                    ))
-                    .id();
-
-                all_chunks.chunks.insert(base, e);
-                // dbg!(&all_chunks.chunks);
-                uploaded += 1;
-                if uploaded >= generatier_info.load_how_many_chunks_per_frame {
-                    break;
+    let pool = AsyncComputeTaskPool::get();
+    let mut uploaded = 0_usize;
+    while uploaded < generatier_info.load_how_many_chunks_per_frame && !active.is_empty() {
+        // one chunk for every loader in turn, the loaders with nothing left drop out
+        active.retain_mut(|(loader, scan)| {
+            if uploaded >= generatier_info.load_how_many_chunks_per_frame {
+                return true;
+            }
+            let order = &queues[loader].order;
+            let base = loop {
+                let base = match order.get(*scan) {
+                    Some(base) => *base,
+                    None => return false,
+                };
+                *scan += 1;
+                if all_chunks.chunks.contains_key(&base) {
+                    continue;
+                }
+                if unloaded.contains(base) {
+                    break base;
+                }
+                match &save {
+                    Some(save) if !regions.ready(save, base) => continue,
+                    _ => break base,
                 }
+            };
+            let e = spawn_chunk(&mut commands, base);
+            all_chunks.chunks.insert(base, e);
+            if let Some(chunk) = unloaded.take(base) {
+                commands.entity(e).insert((chunk, ChunkState::Generated));
+            } else {
+                let generator = generator.0.clone();
+                let seed = generatier_info.seed;
+                let stored = match save.as_mut() {
+                    Some(save) if save.has_region(region_of(base)) => {
+                        match save.get_compressed(base) {
+                            Ok(v) => v.map(|v| v.to_vec()),
+                            Err(e) => {
+                                warn!("generating {base:?} as its saved region is broken: {e}");
+                                None
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                let task = pool.spawn(async move {
+                    if let Some(data) = stored {
+                        match decompress_chunk(base, &data) {
+                            Ok(mut c) => {
+                                c.open_sky = generator.open_sky(base, seed);
+                                return c;
+                            }
+                            Err(e) => warn!("regenerating the broken saved chunk {base:?}: {e}"),
+                        }
+                    }
+                    generator.generate_chunk(base, seed)
+                });
+                pending.tasks.insert(base, (e, task));
             }
+            uploaded += 1;
+            true
+        });
+    }
+}
+
+/// hand the regions that were read to the `WorldSave`
+pub fn finish_regions(mut regions: ResMut<PendingRegions>, save: Option<ResMut<WorldSave>>) {
+    let mut save = match save {
+        Some(v) => v,
+        None => return,
+    };
+    let PendingRegions { tasks, failed } = &mut *regions;
+    tasks.retain(|region, task| {
+        if !task.is_finished() {
+            return true;
+        }
+        match future::block_on(task) {
+            Ok(r) => save.insert_region(*region, r),
+            Err(e) => {
+                warn!("generating the chunks of the region {region:?} as it can not be read: {e}");
+                failed.insert(*region);
+            }
+        }
+        false
+    });
+}
+
+/// insert the chunks whose generation finished into their entities
+///
+/// runs after `delete_chunks`, which takes the tasks of the chunks it despawns
+pub fn finish_chunks(
+    mut pending: ResMut<PendingChunks>,
+    all_chunks: Res<AllChunks>,
+    mut commands: Commands,
+) {
+    pending.tasks.retain(|base, (e, task)| {
+        if !task.is_finished() {
+            return true;
+        }
+        let chunk = future::block_on(task);
+        if all_chunks.chunks.get(base) == Some(e) {
+            commands.entity(*e).insert((chunk, ChunkState::Generated));
         }
+        false
     });
 }
 
+/// unload the chunks no loader keeps within `GeneratorInfo::unload_margin`, into `UnloadedChunks`
 pub fn delete_chunks(
-    playes: Query<&Transform, With<Camera>>,
+    loaders: LoaderQuery,
+    chunks: Query<&Chunk>,
     mut all_chunks: ResMut<AllChunks>,
+    mut pending: ResMut<PendingChunks>,
+    mut unloaded: ResMut<UnloadedChunks>,
     generatier_info: Res<GeneratorInfo>,
     mut commands: Commands,
 ) {
-    playes.for_each(|transform| {
-        let center_pos = Pos::from_xyz(
-            transform.translation.x as i64,
-            transform.translation.y as i64,
-            transform.translation.z as i64,
-        );
-
-        let center_pos = Pos::from_xyz(
-            center_pos.x() - (center_pos.x().rem_euclid(CHUNK_SIZE as i64)),
-            center_pos.y() - (center_pos.y().rem_euclid(CHUNK_SIZE as i64)),
-            center_pos.z() - (center_pos.z().rem_euclid(CHUNK_SIZE as i64)),
+    let mut v = HashSet::new();
+    for (_, transform, loader) in loaders.iter() {
+        let loader = loader
+            .copied()
+            .unwrap_or_else(|| ChunkLoader::from_info(&generatier_info));
+        v.extend(
+            loader
+                .with_margin(generatier_info.unload_margin)
+                .chunks_around(transform),
         );
+    }
 
-        let r1 = Pos::from_xyz(
-            generatier_info.range_xz as i64,
-            generatier_info.range_yp as i64,
-            generatier_info.range_xz as i64,
-        );
-        let r2 = -Pos::from_xyz(
-            generatier_info.range_xz as i64,
-            generatier_info.range_yn as i64,
-            generatier_info.range_xz as i64,
-        );
-        let v = Pos::iter_range(r1, r2)
-            .filter(|p| {
-                p.x() * p.x() + p.y() * p.y() + p.z() * p.z()
-                    < (generatier_info.range_r * generatier_info.range_r) as i64
-            })
-            .map(|i| {
-                {
-                    //dbg!(i);
-                    let base = center_pos + i * CHUNK_SIZE as i64;
-                    base
-                }
-            })
-            .collect::<HashSet<_>>();
-
-        let to_delete = all_chunks
-            .chunks
-            .iter()
-            .filter(|(k, _)| !v.contains(k))
-            .map(|(k, v)| (*k, *v))
-            .collect::<Vec<_>>();
-        for (i, (k, v)) in to_delete.into_iter().enumerate() {
-            if i >= generatier_info.unload_how_many_chunks_per_frame {
-                return;
-            }
-            if let Some(mut e) = commands.get_entity(v) {
-                e.despawn();
+    let to_delete = all_chunks
+        .chunks
+        .iter()
+        .filter(|(k, _)| !v.contains(k))
+        .map(|(k, v)| (*k, *v))
+        .take(generatier_info.unload_how_many_chunks_per_frame)
+        .collect::<Vec<_>>();
+    for (k, v) in to_delete {
+        // chunks that left the range while still generating are cancelled,
+        // the ones that just finished are kept like loaded chunks
+        if let Some((_, mut task)) = pending.tasks.remove(&k) {
+            if task.is_finished() {
+                unloaded.insert(future::block_on(&mut task));
             }
-            all_chunks.chunks.remove(&k);
         }
-    });
+        if let Ok(chunk) = chunks.get(v) {
+            unloaded.insert(chunk.clone());
+        }
+        if let Some(e) = commands.get_entity(v) {
+            e.despawn_recursive();
+        }
+        all_chunks.chunks.remove(&k);
+    }
+}
+
+/// on `SaveWorld`, write the modified chunks and mark them unmodified
+pub fn save_chunks(
+    mut events: EventReader<SaveWorld>,
+    save: Option<ResMut<WorldSave>>,
+    mut chunks: Query<&mut Chunk>,
+    mut unloaded: ResMut<UnloadedChunks>,
+    registry: Res<BlockRegistry>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+    let mut save = match save {
+        Some(v) => v,
+        None => {
+            warn!("there is no WorldSave to save the world to");
+            return;
+        }
+    };
+    let modified = chunks
+        .iter()
+        .filter(|c| c.modified)
+        .chain(unloaded.modified());
+    if let Err(e) = save_world(&mut save, modified, &registry) {
+        error!("saving the world to {:?} failed: {e}", save.dir());
+        return;
+    }
+    for mut c in chunks.iter_mut() {
+        if c.modified {
+            c.modified = false;
+        }
+    }
+    unloaded.mark_saved();
 }
 
 #[test]
@@ -172,3 +528,317 @@ fn a() {
     dbg!(3_i32.rem_euclid(10));
     dbg!((-3_i32).rem_euclid(10));
 }
+
+#[test]
+fn test_load_order() {
+    let c = CHUNK_SIZE as i64;
+    let chunk = |x: i64, y: i64, z: i64| Pos::from_xyz(x * c, y * c, z * c);
+    // in the middle of the chunk at 0 0 0, looking along -z
+    let eye = Vec3::splat(CHUNK_SIZE as f32 / 2.0);
+    let order = load_order(
+        [
+            chunk(0, 0, 2),
+            chunk(0, 0, -3),
+            chunk(0, 0, -2),
+            chunk(0, 0, 0),
+            chunk(3, 0, 0),
+        ]
+        .into_iter(),
+        eye,
+        Vec3::NEG_Z,
+        std::f32::consts::FRAC_PI_4,
+    );
+    assert_eq!(
+        order,
+        vec![
+            chunk(0, 0, 0),
+            chunk(0, 0, -2),
+            chunk(0, 0, -3),
+            chunk(0, 0, 2),
+            chunk(3, 0, 0),
+        ]
+    );
+
+    // right behind the viewer comes after far ahead of it
+    let order = load_order(
+        [chunk(0, 0, 1), chunk(0, 0, -3)].into_iter(),
+        eye,
+        Vec3::NEG_Z,
+        std::f32::consts::FRAC_PI_4,
+    );
+    assert_eq!(order, vec![chunk(0, 0, -3), chunk(0, 0, 1)]);
+
+    // the distance is from the viewer, not from the chunk it is in
+    let eye = Vec3::new(15.9, 8.0, 8.0);
+    let order = load_order(
+        [chunk(-1, 0, 0), chunk(1, 0, 0)].into_iter(),
+        eye,
+        Vec3::Y,
+        0.0,
+    );
+    assert_eq!(order, vec![chunk(1, 0, 0), chunk(-1, 0, 0)]);
+
+    let order = load_order(
+        [chunk(0, 0, 2), chunk(0, 0, -3)].into_iter(),
+        Vec3::ZERO,
+        Vec3::NEG_Z,
+        0.0,
+    );
+    assert_eq!(order[0], chunk(0, 0, 2));
+}
+
+#[test]
+fn test_custom_generator() {
+    use crate::chunk::{blocks::BlockId, chunk::ChunkGeneratorBasic, plugin::run_until};
+
+    struct Filled;
+    impl ChunkGeneratorBasic for Filled {
+        fn generate_base_blocks(
+            &self,
+            _chunk_base_position: Pos,
+            _seed: Seed,
+        ) -> Box<[[[BlockId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]> {
+            Box::new([[[7_u64.into(); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE])
+        }
+    }
+
+    let mut app = App::new();
+    app.add_plugin(super::ChunkGeneratorPlugin::new(
+        GeneratorInfo {
+            range_xz: 1,
+            range_yp: 1,
+            range_yn: 1,
+            range_r: 2,
+            ..Default::default()
+        },
+        Filled,
+    ));
+    app.add_plugin(TaskPoolPlugin::default());
+    app.world.spawn((Camera::default(), Transform::default()));
+    app.update();
+    assert_eq!(app.world.resource::<PendingChunks>().len(), 27);
+    let mut states = app.world.query::<&ChunkState>();
+    assert_eq!(
+        states
+            .iter(&app.world)
+            .filter(|s| **s == ChunkState::Generating)
+            .count(),
+        27
+    );
+
+    run_until(&mut app, |world| {
+        world.resource::<PendingChunks>().is_empty()
+    });
+
+    let mut chunks = app.world.query::<&Chunk>();
+    assert_eq!(chunks.iter(&app.world).count(), 27);
+    assert!(chunks
+        .iter(&app.world)
+        .all(|c| c.blocks.single() == Some(7_u64.into())));
+    assert!(states.iter(&app.world).all(|s| *s == ChunkState::Generated));
+}
+
+#[test]
+fn test_multiple_loaders() {
+    use crate::chunk::plugin::run_until;
+
+    let mut app = App::new();
+    app.add_plugin(TaskPoolPlugin::default())
+        .add_plugin(super::ChunkGeneratorPlugin::new(
+            GeneratorInfo {
+                load_how_many_chunks_per_frame: 4,
+                ..Default::default()
+            },
+            crate::chunk::chunk::simple_generator::SimpleGenerator,
+        ));
+    let loader = ChunkLoader {
+        range_xz: 1,
+        range_yp: 0,
+        range_yn: 0,
+        range_r: 2,
+    };
+    app.world
+        .spawn((loader, Transform::from_xyz(0.0, 0.0, 0.0)));
+    app.world
+        .spawn((loader, Transform::from_xyz(1000.0, 0.0, 0.0)));
+
+    // the loaders share the chunks of a frame
+    app.update();
+    let all_chunks = app.world.resource::<AllChunks>();
+    assert_eq!(all_chunks.chunks.len(), 4);
+    assert!(all_chunks.chunks.contains_key(&Pos::from_xyz(0, 0, 0)));
+    assert!(all_chunks.chunks.contains_key(&Pos::from_xyz(992, 0, 0)));
+
+    run_until(&mut app, |world| {
+        world.resource::<AllChunks>().chunks.len() == 18
+            && world.resource::<PendingChunks>().is_empty()
+    });
+    // both loaders keep their 3x3 chunks even after more frames of unloading
+    app.update();
+    let all_chunks = app.world.resource::<AllChunks>();
+    assert_eq!(all_chunks.chunks.len(), 18);
+    assert!(all_chunks.chunks.contains_key(&Pos::from_xyz(0, 0, 0)));
+    assert!(all_chunks.chunks.contains_key(&Pos::from_xyz(992, 0, 0)));
+}
+
+#[test]
+fn test_unloaded_chunks() {
+    let chunk_at = |x: i64| Chunk {
+        base_pos_of_chunk: Pos::from_xyz(x, 0, 0),
+        ..Default::default()
+    };
+    let mut unloaded = UnloadedChunks::new(2);
+    unloaded.insert(chunk_at(0));
+    unloaded.insert(Chunk {
+        modified: true,
+        ..chunk_at(16)
+    });
+    unloaded.insert(chunk_at(32));
+    unloaded.insert(chunk_at(48));
+
+    assert!(!unloaded.contains(Pos::from_xyz(0, 0, 0)));
+    assert!(unloaded.contains(Pos::from_xyz(16, 0, 0)));
+    assert_eq!(unloaded.len(), 3);
+    assert_eq!(unloaded.modified().count(), 1);
+
+    assert!(unloaded.take(Pos::from_xyz(32, 0, 0)).is_some());
+    assert!(unloaded.take(Pos::from_xyz(32, 0, 0)).is_none());
+}
+
+#[test]
+fn test_unload_generated_chunk() {
+    let mut app = App::new();
+    app.add_plugin(TaskPoolPlugin::default())
+        .add_plugin(super::ChunkGeneratorPlugin::default());
+    let loader = ChunkLoader {
+        range_xz: 0,
+        range_yp: 0,
+        range_yn: 0,
+        range_r: 1,
+    };
+    let e = app.world.spawn((loader, Transform::default())).id();
+    app.update();
+    assert_eq!(app.world.resource::<PendingChunks>().len(), 1);
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    // the chunk finished and left the range in the same frame, it is kept and not inserted
+    app.world.get_mut::<Transform>(e).unwrap().translation.x = 1000.0;
+    app.update();
+    assert!(!app
+        .world
+        .resource::<PendingChunks>()
+        .contains(Pos::from_xyz(0, 0, 0)));
+    assert!(app
+        .world
+        .resource::<UnloadedChunks>()
+        .contains(Pos::from_xyz(0, 0, 0)));
+    let mut chunks = app.world.query::<&Chunk>();
+    assert!(chunks
+        .iter(&app.world)
+        .all(|c| c.base_pos_of_chunk != Pos::from_xyz(0, 0, 0)));
+}
+
+#[test]
+fn test_unload_margin() {
+    use crate::chunk::plugin::run_until;
+
+    let mut app = App::new();
+    app.add_plugin(TaskPoolPlugin::default())
+        .add_plugin(super::ChunkGeneratorPlugin::new(
+            GeneratorInfo {
+                unload_margin: 1,
+                ..Default::default()
+            },
+            crate::chunk::chunk::simple_generator::SimpleGenerator,
+        ));
+    let loader = ChunkLoader {
+        range_xz: 1,
+        range_yp: 0,
+        range_yn: 0,
+        range_r: 2,
+    };
+    let e = app.world.spawn((loader, Transform::default())).id();
+    run_until(&mut app, |world| {
+        world.resource::<AllChunks>().chunks.len() == 9
+            && world.resource::<PendingChunks>().is_empty()
+    });
+
+    // one chunk over, the chunks at x = -16 are inside the margin
+    app.world.get_mut::<Transform>(e).unwrap().translation.x = 16.0;
+    app.update();
+    let all_chunks = app.world.resource::<AllChunks>();
+    assert!(all_chunks.chunks.contains_key(&Pos::from_xyz(-16, 0, 0)));
+
+    // two chunks over, they are unloaded into the cache
+    app.world.get_mut::<Transform>(e).unwrap().translation.x = 32.0;
+    app.update();
+    let all_chunks = app.world.resource::<AllChunks>();
+    assert!(!all_chunks.chunks.contains_key(&Pos::from_xyz(-16, 0, 0)));
+    assert!(app
+        .world
+        .resource::<UnloadedChunks>()
+        .contains(Pos::from_xyz(-16, 0, 0)));
+}
+
+#[test]
+fn test_stored_chunks() {
+    use crate::chunk::{blocks::IdMapping, plugin::run_until, storage::load_world};
+
+    let registry = BlockRegistry::default();
+
+    let dir = std::env::temp_dir().join(format!("phyvox_test_stored_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut save = WorldSave::new(&dir, IdMapping::default());
+    let stored = Chunk {
+        base_pos_of_chunk: Pos::from_xyz(-16, 0, 0),
+        ..Chunk::new_filled_with_id(5_u64.into())
+    };
+    save_world(&mut save, [&stored], &registry).unwrap();
+
+    let mut app = App::new();
+    app.add_plugin(TaskPoolPlugin::default())
+        .add_plugin(super::ChunkGeneratorPlugin::default())
+        .insert_resource(load_world(&dir, &registry, &IdMapping::default()).unwrap());
+    let loader = ChunkLoader {
+        range_xz: 1,
+        range_yp: 0,
+        range_yn: 0,
+        range_r: 2,
+    };
+    app.world.spawn((loader, Transform::default()));
+    // the chunks wait for their regions to be read
+    app.update();
+    assert!(app.world.resource::<AllChunks>().chunks.is_empty());
+    assert!(!app.world.resource::<PendingRegions>().is_empty());
+    run_until(&mut app, |world| {
+        world.resource::<AllChunks>().chunks.len() == 9
+            && world.resource::<PendingChunks>().is_empty()
+    });
+    app.update();
+
+    let chunk_at = |app: &App, base: Pos| app.world.resource::<AllChunks>().chunks[&base];
+    let e = chunk_at(&app, Pos::from_xyz(-16, 0, 0));
+    let c = app.world.get::<Chunk>(e).unwrap();
+    assert_eq!(c.blocks.single(), Some(5_u64.into()));
+    // the sky above a saved chunk still comes from the generator
+    assert!(c.open_sky.is_some());
+
+    let e = chunk_at(&app, Pos::from_xyz(0, 0, 0));
+    app.world
+        .get_mut::<Chunk>(e)
+        .unwrap()
+        .set_block(Pos::from_xyz(1, 2, 3), 5_u64.into());
+    app.world.send_event(SaveWorld);
+    app.update();
+    assert!(!app.world.get::<Chunk>(e).unwrap().modified);
+
+    let mut loaded = load_world(&dir, &registry, &IdMapping::default()).unwrap();
+    let c = loaded
+        .load_chunk(Pos::from_xyz(-16, 0, 0))
+        .unwrap()
+        .unwrap();
+    assert_eq!(c.blocks.single(), Some(5_u64.into()));
+    let c = loaded.load_chunk(Pos::from_xyz(0, 0, 0)).unwrap().unwrap();
+    assert_eq!(c.blocks[Pos::from_xyz(1, 2, 3)], 5_u64.into());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -8,3 +8,5 @@ pub mod chunk;
 pub mod plugin;
 
 pub mod generator_plugin;
+
+pub mod storage;
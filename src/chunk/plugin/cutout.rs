@@ -0,0 +1,97 @@
+use bevy::{pbr::NotShadowCaster, prelude::*};
+
+use crate::chunk::chunk::Chunk;
+
+use super::{ChunkInfo, ChunkMaterial, ChunkState};
+
+/// the child of a chunk entity drawing the models of its special blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct CutoutMeshEntity(pub Entity);
+
+/// upload the cutout meshes that `change_mesh` left in the chunks
+///
+/// the child casts no shadow, the shadow pass does not cut out the holes of the models
+pub fn change_cutout_mesh(
+    mut chunks: Query<(Entity, &mut Chunk, Option<&CutoutMeshEntity>), Changed<ChunkState>>,
+    children: Query<&Handle<Mesh>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    chunk_info: Res<ChunkInfo>,
+    mut commands: Commands,
+) {
+    chunks.for_each_mut(|(e, mut chunk, child)| {
+        let mesh = match chunk.get_cutout_bevy_mesh() {
+            Some(m) => m,
+            None => return,
+        };
+        if let Some(h) = child.and_then(|c| children.get(c.0).ok()) {
+            meshes.remove(h);
+        }
+        if mesh.count_vertices() == 0 {
+            if let Some(child) = child {
+                commands.entity(child.0).despawn_recursive();
+                commands.entity(e).remove::<CutoutMeshEntity>();
+            }
+            return;
+        }
+
+        let handle = meshes.add(mesh);
+        match child {
+            Some(child) => {
+                commands.entity(child.0).insert(handle);
+            }
+            None => {
+                let child = commands
+                    .spawn((
+                        MaterialMeshBundle::<ChunkMaterial> {
+                            mesh: handle,
+                            material: chunk_info.cutout_material.clone(),
+                            ..default()
+                        },
+                        NotShadowCaster,
+                    ))
+                    .id();
+                commands
+                    .entity(e)
+                    .insert(CutoutMeshEntity(child))
+                    .add_child(child);
+            }
+        }
+        commands
+            .entity(e)
+            .insert((Visibility::default(), ComputedVisibility::default()));
+    });
+}
+
+#[test]
+fn test_cutout_mesh() {
+    use super::*;
+    use crate::chunk::{blocks::BlockRegistry, Pos};
+
+    let registry = BlockRegistry::load("assets/blocks.json").unwrap();
+    let id_mapping = registry.id_mapping();
+    let fern = id_mapping.id_of(registry.get("fern").unwrap()).unwrap();
+    let mut app = test_app(id_mapping);
+
+    // a fern on a stone
+    let mut chunk = Chunk::default();
+    chunk.set_block(Pos::from_xyz(0, 0, 0), 1_u64.into());
+    chunk.set_block(Pos::from_xyz(0, 1, 0), fern);
+    let e = app.world.spawn(chunk).id();
+    run_until(&mut app, |world| {
+        world.get::<ChunkState>(e) == Some(&ChunkState::Uploaded)
+    });
+    app.update();
+
+    let child = app.world.get::<CutoutMeshEntity>(e).unwrap().0;
+    assert!(app.world.get::<NotShadowCaster>(child).is_some());
+    let meshes = app.world.resource::<Assets<Mesh>>();
+    let cutout = meshes
+        .get(app.world.get::<Handle<Mesh>>(child).unwrap())
+        .unwrap();
+    assert!(cutout.count_vertices() > 0);
+    // the opaque mesh only has the stone
+    let opaque = meshes
+        .get(app.world.get::<Handle<Mesh>>(e).unwrap())
+        .unwrap();
+    assert_eq!(opaque.count_vertices(), 6 * 4);
+}
@@ -0,0 +1,126 @@
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_asset::RenderAssets,
+        render_resource::{
+            AsBindGroup, AsBindGroupError, BindGroupLayout, Face, PreparedBindGroup,
+            RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+        },
+        renderer::RenderDevice,
+        texture::FallbackImage,
+    },
+};
+
+use crate::chunk::chunk::ATTRIBUTE_TILE;
+
+/// the vertex and fragment shader of `ChunkMaterial`
+pub const CHUNK_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2_930_113_013);
+
+/// a `StandardMaterial` whose textures come from the tile of the atlas of every vertex,
+/// so a texture repeats across a quad with a uv larger than 1, see `Mesh::tile`
+///
+/// only for chunk meshes, they have every attribute the shader reads
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "6f0f3c64-5d0c-4a57-9d0b-2b8a4f6c1e21"]
+pub struct ChunkMaterial(pub StandardMaterial);
+
+/// the parts of the `StandardMaterial` its pipeline depends on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkMaterialKey {
+    normal_map: bool,
+    cull_mode: Option<Face>,
+    depth_bias: i32,
+}
+
+impl From<&StandardMaterial> for ChunkMaterialKey {
+    fn from(material: &StandardMaterial) -> Self {
+        ChunkMaterialKey {
+            normal_map: material.normal_map_texture.is_some(),
+            cull_mode: material.cull_mode,
+            depth_bias: material.depth_bias as i32,
+        }
+    }
+}
+
+/// the bindings of the `StandardMaterial`, the shader reads them like `pbr.wgsl` does
+impl AsBindGroup for ChunkMaterial {
+    type Data = ChunkMaterialKey;
+
+    fn as_bind_group(
+        &self,
+        layout: &BindGroupLayout,
+        render_device: &RenderDevice,
+        images: &RenderAssets<Image>,
+        fallback_image: &FallbackImage,
+    ) -> Result<PreparedBindGroup<Self::Data>, AsBindGroupError> {
+        let prepared = self
+            .0
+            .as_bind_group(layout, render_device, images, fallback_image)?;
+        Ok(PreparedBindGroup {
+            bindings: prepared.bindings,
+            bind_group: prepared.bind_group,
+            data: ChunkMaterialKey::from(&self.0),
+        })
+    }
+
+    fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+        StandardMaterial::bind_group_layout(render_device)
+    }
+}
+
+impl Material for ChunkMaterial {
+    fn vertex_shader() -> ShaderRef {
+        CHUNK_SHADER_HANDLE.typed().into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        CHUNK_SHADER_HANDLE.typed().into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.0.alpha_mode
+    }
+
+    fn depth_bias(&self) -> f32 {
+        self.0.depth_bias
+    }
+
+    /// what `StandardMaterial::specialize` does, and the tile attribute in the main pass,
+    /// the shadow and depth passes use bevy's prepass shader that does not read it
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        if descriptor.vertex.shader == CHUNK_SHADER_HANDLE.typed::<Shader>() {
+            descriptor.vertex.buffers = vec![layout.get_layout(&[
+                Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+                Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+                Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+                Mesh::ATTRIBUTE_TANGENT.at_shader_location(3),
+                Mesh::ATTRIBUTE_COLOR.at_shader_location(4),
+                ATTRIBUTE_TILE.at_shader_location(5),
+            ])?];
+        }
+        if key.bind_group_data.normal_map {
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment
+                    .shader_defs
+                    .push("STANDARDMATERIAL_NORMAL_MAP".into());
+            }
+        }
+        descriptor.primitive.cull_mode = key.bind_group_data.cull_mode;
+        if let Some(label) = &mut descriptor.label {
+            *label = format!("chunk_{}", *label).into();
+        }
+        if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
+            depth_stencil.bias.constant = key.bind_group_data.depth_bias;
+        }
+        Ok(())
+    }
+}
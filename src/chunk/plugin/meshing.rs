@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+
+use crate::chunk::{
+    blocks::{Mesh as ChunkMesh, QuadGroup},
+    chunk::{Chunk, ChunkClass, ChunkNeighbours, NeighbourBorders},
+};
+
+use super::{
+    ChunkInfo, ChunkMaterial, ChunkState, CutoutMeshEntity, LoadedChunks, TransparentMeshEntity,
+};
+
+#[derive(Debug)]
+struct MeshJob {
+    version: u64,
+    /// the quad group, and the opaque, the transparent and the cutout mesh
+    task: Task<(QuadGroup, (ChunkMesh, ChunkMesh, ChunkMesh))>,
+}
+
+/// quad groups and meshes being built on the `AsyncComputeTaskPool`, by chunk entity
+///
+/// dropping a job cancels it
+#[derive(Debug, Default, Resource)]
+pub struct MeshingJobs {
+    jobs: HashMap<Entity, MeshJob>,
+}
+
+impl MeshingJobs {
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+}
+
+/// copy the blocks of every chunk that became `Generated` or `Dirty`, and the borders of its neighbours,
+/// into a meshing task
+///
+/// a chunk that kept its quad group, because only its light changed, only has its meshes built
+///
+/// empty chunks and chunks buried between solid neighbours skip meshing, they go straight to
+/// `ChunkState::Uploaded` and lose the meshes they had
+pub fn start_meshing_jobs(
+    mut changed: Query<(Entity, &mut ChunkState), Changed<ChunkState>>,
+    chunks: Query<&Chunk>,
+    children: Query<(Option<&TransparentMeshEntity>, Option<&CutoutMeshEntity>)>,
+    loaded: Res<LoadedChunks>,
+    chunk_info: Res<ChunkInfo>,
+    mut jobs: ResMut<MeshingJobs>,
+    mut commands: Commands,
+) {
+    let pool = AsyncComputeTaskPool::get();
+    for (e, mut state) in changed.iter_mut() {
+        if !state.needs_quads() {
+            continue;
+        }
+        let chunk = match chunks.get(e) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+        if matches!(jobs.jobs.get(&e), Some(job) if job.version == chunk.version) {
+            continue;
+        }
+
+        let neighbours = ChunkNeighbours::from_fn(|face| {
+            let e = loaded.get(LoadedChunks::neighbour_pos(chunk.base_pos_of_chunk, face))?;
+            chunks.get(e).ok()
+        });
+        if chunk.class == ChunkClass::Empty || chunk.is_occluded(&neighbours) {
+            jobs.jobs.remove(&e);
+            *state = ChunkState::Uploaded;
+            commands
+                .entity(e)
+                .remove::<(Handle<Mesh>, Handle<ChunkMaterial>)>()
+                .remove::<(Visibility, ComputedVisibility)>();
+            if let Ok((transparent, cutout)) = children.get(e) {
+                for child in transparent
+                    .map(|c| c.0)
+                    .into_iter()
+                    .chain(cutout.map(|c| c.0))
+                {
+                    commands.entity(child).despawn_recursive();
+                }
+                commands
+                    .entity(e)
+                    .remove::<(TransparentMeshEntity, CutoutMeshEntity)>();
+            }
+            continue;
+        }
+        let borders = NeighbourBorders::from_neighbours(&neighbours);
+        let snapshot = Chunk {
+            base_pos_of_chunk: chunk.base_pos_of_chunk,
+            blocks: chunk.blocks.clone(),
+            class: chunk.class,
+            light: chunk.light.clone(),
+            ..Default::default()
+        };
+        let quads = chunk.quad_group.clone();
+        let id_mapping = chunk_info.id_mapping.clone();
+        let mesh_mode = chunk_info.mesh_mode;
+
+        let task = pool.spawn(async move {
+            let quads = quads.unwrap_or_else(|| snapshot.build_quad_group(&id_mapping, &borders));
+            let meshes = snapshot.build_meshes(&quads, &id_mapping, mesh_mode, &borders);
+            (quads, meshes)
+        });
+        // replaces and cancels the job of an older version
+        jobs.jobs.insert(
+            e,
+            MeshJob {
+                version: chunk.version,
+                task,
+            },
+        );
+    }
+}
+
+/// hand finished quad groups and meshes to their chunks, `change_mesh` uploads them
+pub fn finish_meshing_jobs(
+    mut chunks: Query<(&mut Chunk, &mut ChunkState)>,
+    mut jobs: ResMut<MeshingJobs>,
+) {
+    jobs.jobs.retain(|e, job| {
+        let (mut chunk, mut state) = match chunks.get_mut(*e) {
+            Ok(c) => c,
+            Err(_) => return false,
+        };
+        if !job.task.is_finished() {
+            return true;
+        }
+        let (quads, (mesh, transparent_mesh, cutout_mesh)) = future::block_on(&mut job.task);
+        if chunk.version == job.version {
+            chunk.set_quad_group(quads);
+            chunk.mesh = Some(mesh);
+            chunk.transparent_mesh = Some(transparent_mesh);
+            chunk.cutout_mesh = Some(cutout_mesh);
+            *state = ChunkState::Meshed;
+        } else if chunk.quad_group.is_none() {
+            // the chunk was edited while the job ran, it gets a new job
+            *state = ChunkState::Dirty;
+        }
+        false
+    });
+}
+
+#[test]
+fn test_edit_invalidates_job() {
+    use crate::chunk::{blocks::IdMapping, Pos};
+
+    let mut app = super::test_app(default());
+
+    let e = app
+        .world
+        .spawn((
+            Chunk::new_filled_with_id(1_u64.into()),
+            ChunkState::Generated,
+        ))
+        .id();
+    app.update();
+    app.world
+        .get_mut::<Chunk>(e)
+        .unwrap()
+        .set_block(Pos::from_xyz(0, 0, 0), 0_u64.into());
+
+    // the outdated job is dropped and the chunk gets a new one
+    super::run_until(&mut app, |world| {
+        world.get::<ChunkState>(e) == Some(&ChunkState::Uploaded)
+    });
+    assert!(app.world.resource::<MeshingJobs>().is_empty());
+
+    let chunk = app.world.get::<Chunk>(e).unwrap();
+    let expected = chunk.build_quad_group(&IdMapping::default(), &ChunkNeighbours::default());
+    assert_eq!(chunk.get_quad_group(), Some(&expected));
+    assert!(app.world.get::<Handle<Mesh>>(e).is_some());
+}
+
+#[test]
+fn test_skip_empty_and_buried_chunks() {
+    use crate::chunk::Pos;
+
+    let mut app = super::test_app(default());
+
+    let mut spawn = |base: Pos, mut chunk: Chunk| {
+        chunk.base_pos_of_chunk = base;
+        app.world.spawn((chunk, ChunkState::Generated)).id()
+    };
+    let empty = spawn(Pos::from_xyz(0, 64, 0), Chunk::default());
+    let buried = spawn(Pos::default(), Chunk::new_filled_with_id(1_u64.into()));
+    for face in crate::chunk::BlockFace::iter_all() {
+        spawn(
+            LoadedChunks::neighbour_pos(Pos::default(), face),
+            Chunk::new_filled_with_id(1_u64.into()),
+        );
+    }
+    app.update();
+
+    // no job was started for them and they have nothing to show
+    for e in [empty, buried] {
+        assert_eq!(app.world.get::<ChunkState>(e), Some(&ChunkState::Uploaded));
+        let chunk = app.world.get::<Chunk>(e).unwrap();
+        assert!(chunk.quad_group.is_none() && chunk.mesh.is_none());
+    }
+    assert_eq!(app.world.resource::<MeshingJobs>().len(), 6);
+}
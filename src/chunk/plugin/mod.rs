@@ -1,21 +1,101 @@
-use bevy::prelude::*;
+use bevy::{asset::load_internal_asset, ecs::schedule::SystemConfigs, prelude::*};
+
+use crate::chunk::blocks::{BlockAtlas, BlockRegistry};
+
+mod material;
+pub use material::*;
 
 mod systems;
 pub use systems::*;
 
+mod world;
+pub use world::*;
+
+mod meshing;
+pub use meshing::*;
+
+mod state;
+pub use state::*;
+
+mod transparent;
+pub use transparent::*;
+
+mod cutout;
+pub use cutout::*;
+
 #[derive(Debug, Clone, Copy, Component, PartialEq, Eq, Hash)]
 pub struct ChunkPlugin;
 
+impl ChunkPlugin {
+    /// the systems that take a chunk from its blocks to its uploaded meshes, in order
+    pub fn systems() -> SystemConfigs {
+        (
+            track_chunks,
+            classify_chunks,
+            light_loaded_chunks,
+            mark_edited_chunks,
+            start_meshing_jobs,
+            finish_meshing_jobs,
+            generate_mesh,
+            change_mesh,
+            change_transparent_mesh,
+            change_cutout_mesh,
+            sort_transparent_meshes,
+        )
+            .chain()
+    }
+}
+
 impl Plugin for ChunkPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(generate_quad_group)
-            .add_system(generate_mesh)
+        load_internal_asset!(app, CHUNK_SHADER_HANDLE, "chunk.wgsl", Shader::from_wgsl);
+        app.add_plugin(MaterialPlugin::<ChunkMaterial>::default())
+            .init_resource::<LoadedChunks>()
+            .init_resource::<BlockRegistry>()
+            .init_resource::<BlockAtlas>()
+            .init_resource::<MeshingJobs>()
+            .add_event::<ChunkLoaded>()
+            .add_event::<ChunkUnloaded>()
+            .add_systems(ChunkPlugin::systems())
             .add_system(insert_material)
             .add_system(insert_pbr::<Visibility>)
             //.add_system(insert_pbr::<GlobalTransform>)
             .add_system(insert_pbr::<ComputedVisibility>)
-            .add_system(change_mesh)
-            .add_startup_systems((setup, load_png))
-            .add_system(set_png);
+            .add_startup_system(setup);
+    }
+}
+
+/// an app with the resources and `ChunkPlugin::systems`, without the rendering the plugin needs
+#[cfg(test)]
+pub(crate) fn test_app(id_mapping: crate::chunk::blocks::IdMapping) -> App {
+    let mut app = App::new();
+    app.add_plugin(TaskPoolPlugin::default())
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<Mesh>()
+        .init_resource::<LoadedChunks>()
+        .init_resource::<MeshingJobs>()
+        .insert_resource(ChunkInfo {
+            id_mapping,
+            material: default(),
+            transparent_material: default(),
+            cutout_material: default(),
+            mesh_mode: crate::chunk::blocks::MeshMode::Greedy,
+        })
+        .add_event::<ChunkLoaded>()
+        .add_event::<ChunkUnloaded>()
+        .add_systems(ChunkPlugin::systems());
+    app
+}
+
+/// update `app` until `done` holds, the tasks of the pools get a millisecond between updates
+#[cfg(test)]
+pub(crate) fn run_until(app: &mut App, done: impl Fn(&World) -> bool) {
+    for _ in 0..1000 {
+        app.update();
+        if done(&app.world) {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
     }
+    panic!("the app never got there");
 }
@@ -0,0 +1,104 @@
+use bevy::prelude::*;
+
+use crate::chunk::{chunk::Chunk, Pos};
+
+/// how far a chunk entity got from its blocks to an uploaded mesh
+///
+/// systems only look at the chunks whose state changed, through `Changed<ChunkState>`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub enum ChunkState {
+    /// the entity is reserved, its `Chunk` is still being generated
+    Generating,
+    /// the blocks are there, nothing was built from them yet
+    #[default]
+    Generated,
+    /// the quad group is up to date, the mesh is not
+    QuadsBuilt,
+    /// the mesh is built and waits for `change_mesh`
+    Meshed,
+    /// the mesh is in `Assets<Mesh>`, or the chunk has nothing to show
+    Uploaded,
+    /// the blocks or a neighbour changed, the quad group has to be built again,
+    /// or only the light changed and the meshes are built again from the quad group the chunk kept
+    Dirty,
+}
+
+impl ChunkState {
+    /// the quad group or the meshes have to be built by a meshing job
+    pub fn needs_quads(&self) -> bool {
+        matches!(self, ChunkState::Generated | ChunkState::Dirty)
+    }
+}
+
+/// sent when a `Chunk` is added to an entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLoaded {
+    pub entity: Entity,
+    pub base_pos_of_chunk: Pos,
+}
+
+/// sent when a `Chunk` is removed or its entity despawned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkUnloaded {
+    pub entity: Entity,
+    pub base_pos_of_chunk: Pos,
+}
+
+/// move chunks that were edited through `Chunk` directly back to `ChunkState::Dirty`
+pub fn mark_edited_chunks(mut chunks: Query<(&Chunk, &mut ChunkState), Changed<Chunk>>) {
+    for (chunk, mut state) in chunks.iter_mut() {
+        let built = matches!(
+            *state,
+            ChunkState::QuadsBuilt | ChunkState::Meshed | ChunkState::Uploaded
+        );
+        if built && chunk.quad_group.is_none() {
+            *state = ChunkState::Dirty;
+        }
+    }
+}
+
+#[test]
+fn test_chunk_lifecycle() {
+    use super::*;
+
+    let mut app = test_app(default());
+    let run_until_uploaded = |app: &mut App, e: Entity| {
+        run_until(app, |world| {
+            world.get::<ChunkState>(e) == Some(&ChunkState::Uploaded)
+        });
+    };
+
+    let mut chunk = Chunk::default();
+    chunk.set_block(Pos::from_xyz(1, 2, 3), 1_u64.into());
+    let e = app.world.spawn(chunk).id();
+    app.update();
+    let loaded = app.world.resource::<Events<ChunkLoaded>>();
+    assert_eq!(
+        loaded.get_reader().iter(loaded).collect::<Vec<_>>(),
+        vec![&ChunkLoaded {
+            entity: e,
+            base_pos_of_chunk: Pos::default()
+        }]
+    );
+    run_until_uploaded(&mut app, e);
+    assert!(app.world.get::<Handle<Mesh>>(e).is_some());
+
+    // nothing changed, so nothing is built again
+    app.update();
+    assert!(app.world.resource::<MeshingJobs>().is_empty());
+
+    app.world
+        .get_mut::<Chunk>(e)
+        .unwrap()
+        .set_block(Pos::from_xyz(1, 2, 4), 1_u64.into());
+    let mesh = app.world.get::<Handle<Mesh>>(e).unwrap().clone();
+    run_until(&mut app, |world| {
+        world.get::<ChunkState>(e) == Some(&ChunkState::Uploaded)
+            && world.get::<Handle<Mesh>>(e) != Some(&mesh)
+    });
+
+    app.world.despawn(e);
+    app.update();
+    let unloaded = app.world.resource::<Events<ChunkUnloaded>>();
+    assert_eq!(unloaded.get_reader().iter(unloaded).count(), 1);
+}
@@ -1,54 +1,113 @@
-use bevy::{
-    prelude::*,
-    render::{
-        render_resource::{FilterMode, SamplerDescriptor},
-        texture::ImageSampler,
-    },
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::chunk::{
+    blocks::{BlockAtlas, BlockRegistry, IdMapping, MeshMode, TextureChannel},
+    chunk::{Chunk, ChunkNeighbours, CHUNK_SIZE},
+    BlockFace, Pos,
 };
 
-use crate::chunk::{blocks::IdMapping, chunk::Chunk};
+use super::{ChunkLoaded, ChunkMaterial, ChunkState, ChunkUnloaded};
 
 #[derive(Debug, Clone, PartialEq, Eq, Resource)]
 pub struct ChunkInfo {
     pub id_mapping: IdMapping,
-    pub material: Handle<StandardMaterial>,
+    pub material: Handle<ChunkMaterial>,
+    /// alpha blended, for the faces of transparent blocks
+    pub transparent_material: Handle<ChunkMaterial>,
+    /// cut out, for the models of special blocks, so plants can have holes
+    pub cutout_material: Handle<ChunkMaterial>,
+    pub mesh_mode: MeshMode,
+}
+
+/// every chunk entity by the base position of the chunk
+#[derive(Debug, Clone, PartialEq, Eq, Default, Resource)]
+pub struct LoadedChunks {
+    chunks: HashMap<Pos, Entity>,
+    positions: HashMap<Entity, Pos>,
+    /// the faces of every chunk with blocks on them, by `BlockFace::to_index`,
+    /// kept for the neighbours once the chunk is gone
+    filled_faces: HashMap<Entity, u8>,
+}
+
+impl LoadedChunks {
+    pub fn get(&self, base_pos_of_chunk: Pos) -> Option<Entity> {
+        self.chunks.get(&base_pos_of_chunk).copied()
+    }
+
+    pub fn insert(&mut self, base_pos_of_chunk: Pos, e: Entity) {
+        self.chunks.insert(base_pos_of_chunk, e);
+        self.positions.insert(e, base_pos_of_chunk);
+    }
+
+    /// returns the base position the entity was loaded at
+    pub fn remove_entity(&mut self, e: Entity) -> Option<Pos> {
+        let pos = self.positions.remove(&e)?;
+        self.filled_faces.remove(&e);
+        if self.chunks.get(&pos) == Some(&e) {
+            self.chunks.remove(&pos);
+        }
+        Some(pos)
+    }
+
+    pub fn neighbour_pos(base_pos_of_chunk: Pos, face: BlockFace) -> Pos {
+        base_pos_of_chunk + face.to_pos() * CHUNK_SIZE as i64
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Pos, Entity)> + '_ {
+        self.chunks.iter().map(|(p, e)| (*p, *e))
+    }
 }
 
+/// the material of every chunk uses the images of the `BlockAtlas`
 pub fn setup(
     mut commands: Commands,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    registry: Res<BlockRegistry>,
+    atlas: Res<BlockAtlas>,
 ) {
-    let material = materials.add(StandardMaterial {
+    let mut image = |channel| Some(images.add(atlas.image(channel).clone()));
+    let material = StandardMaterial {
         base_color: Color::Rgba {
             red: 1.0,
             green: 1.0,
             blue: 1.0,
             alpha: 1.0,
         },
-        base_color_texture: Some(images.get_handle("base_color_texture.png")),
+        base_color_texture: image(TextureChannel::BaseColor),
         emissive: Color::Rgba {
             red: 1.0,
             green: 1.0,
             blue: 1.0,
             alpha: 1.0,
         },
-        emissive_texture: Some(images.get_handle("emissive_texture.png")),
+        emissive_texture: image(TextureChannel::Emissive),
         perceptual_roughness: 1.0,
         metallic: 1.0,
-        metallic_roughness_texture: Some(images.get_handle("metallic_roughness_texture.png")),
+        metallic_roughness_texture: image(TextureChannel::MetallicRoughness),
         reflectance: 0.5,
-        normal_map_texture: Some(images.get_handle("normal_map_texture.png")),
+        normal_map_texture: image(TextureChannel::Normal),
         flip_normal_map_y: false,
-        occlusion_texture: Some(images.get_handle("occlusion_texture.png")),
+        occlusion_texture: image(TextureChannel::Occlusion),
         double_sided: false,
         cull_mode: Some(bevy::render::render_resource::Face::Back),
         unlit: false,
         fog_enabled: true,
         alpha_mode: AlphaMode::Opaque,
         depth_bias: 0.0,
-    });
-    let m = materials.add(
+    };
+    let transparent_material = materials.add(ChunkMaterial(StandardMaterial {
+        alpha_mode: AlphaMode::Blend,
+        ..material.clone()
+    }));
+    let cutout_material = materials.add(ChunkMaterial(StandardMaterial {
+        alpha_mode: AlphaMode::Mask(0.5),
+        ..material.clone()
+    }));
+    let material = materials.add(ChunkMaterial(material));
+    let m = materials.add(ChunkMaterial(
         Color::Rgba {
             red: 1.,
             green: 0.,
@@ -56,51 +115,162 @@ pub fn setup(
             alpha: 1.0,
         }
         .into(),
-    );
+    ));
     commands.insert_resource(ChunkInfo {
-        id_mapping: default(),
+        id_mapping: registry.id_mapping(),
         material,
+        transparent_material,
+        cutout_material,
+        mesh_mode: MeshMode::Greedy,
     })
 }
 
-pub fn load_png(server: Res<AssetServer>) {
-    let _h: Handle<Image> = server.load("base_color_texture.png");
-    let _h: Handle<Image> = server.load("emissive_texture.png");
-    let _h: Handle<Image> = server.load("metallic_roughness_texture.png");
-    let _h: Handle<Image> = server.load("normal_map_texture.png");
-    let _h: Handle<Image> = server.load("occlusion_texture.png");
+/// the faces of the chunk with blocks on them, as a mask of `BlockFace::to_index`
+fn filled_faces(chunk: &Chunk, id_mapping: &IdMapping) -> u8 {
+    BlockFace::iter_all()
+        .filter(|face| !chunk.face_is_empty(*face, id_mapping))
+        .fold(0, |mask, face| mask | 1 << face.to_index())
 }
 
-pub fn set_png(mut materials: ResMut<Assets<Image>>) {
-    for (_, h) in materials.iter_mut() {
-        h.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
-            mag_filter: FilterMode::Nearest,
-            min_filter: FilterMode::Linear,
-            ..default()
+/// keep `LoadedChunks` up to date, send the load and unload events,
+/// and rebuild the borders next to loaded or unloaded chunks
+///
+/// only the neighbours touching blocks of the chunk are rebuilt,
+/// a neighbour still waiting for its quad group keeps its state
+///
+/// chunks spawned without a `ChunkState` get one
+pub fn track_chunks(
+    mut loaded: ResMut<LoadedChunks>,
+    mut chunks: ParamSet<(
+        Query<(Entity, &Chunk, Option<&ChunkState>), Added<Chunk>>,
+        Query<(Entity, &Chunk), Changed<Chunk>>,
+        Query<(&mut Chunk, &mut ChunkState)>,
+    )>,
+    mut removed: RemovedComponents<Chunk>,
+    mut loaded_events: EventWriter<ChunkLoaded>,
+    mut unloaded_events: EventWriter<ChunkUnloaded>,
+    chunk_info: Res<ChunkInfo>,
+    mut commands: Commands,
+) {
+    let mut changed = Vec::new();
+    for e in removed.iter() {
+        let faces = loaded.filled_faces.get(&e).copied().unwrap_or(u8::MAX);
+        if let Some(base_pos_of_chunk) = loaded.remove_entity(e) {
+            changed.push((base_pos_of_chunk, faces));
+            unloaded_events.send(ChunkUnloaded {
+                entity: e,
+                base_pos_of_chunk,
+            });
+        }
+    }
+    for (e, chunk) in chunks.p1().iter() {
+        let faces = filled_faces(chunk, &chunk_info.id_mapping);
+        loaded.filled_faces.insert(e, faces);
+    }
+    for (e, chunk, state) in chunks.p0().iter() {
+        let base_pos_of_chunk = chunk.base_pos_of_chunk;
+        loaded.insert(base_pos_of_chunk, e);
+        changed.push((base_pos_of_chunk, loaded.filled_faces[&e]));
+        if state.is_none() {
+            commands.entity(e).insert(ChunkState::Generated);
+        }
+        loaded_events.send(ChunkLoaded {
+            entity: e,
+            base_pos_of_chunk,
         });
     }
+
+    let mut chunks = chunks.p2();
+    for (pos, faces) in changed {
+        for face in BlockFace::iter_all() {
+            if faces & 1 << face.to_index() == 0 {
+                continue;
+            }
+            let e = match loaded.get(LoadedChunks::neighbour_pos(pos, face)) {
+                Some(e) => e,
+                None => continue,
+            };
+            if let Ok((mut c, mut state)) = chunks.get_mut(e) {
+                c.mark_dirty();
+                if state.needs_quads() {
+                    // a running job read the old border, `start_meshing_jobs` replaces it
+                    state.set_changed();
+                } else {
+                    *state = ChunkState::Dirty;
+                }
+            }
+        }
+    }
 }
 
-pub fn generate_quad_group(mut chunks: Query<&mut Chunk, With<Chunk>>, chunk_info: Res<ChunkInfo>) {
+/// classify newly loaded chunks so empty and buried ones skip meshing
+pub fn classify_chunks(mut chunks: Query<&mut Chunk, Added<Chunk>>, chunk_info: Res<ChunkInfo>) {
     chunks.for_each_mut(|mut x| {
-        if x.quad_group.is_none() {
-            x.generate_quad_group(&chunk_info.id_mapping);
-            //  println!("generate_quad_group for {:?}", &x.base_pos_of_chunk);
-        }
+        x.classify(&chunk_info.id_mapping);
     })
 }
 
-pub fn generate_mesh(mut chunks: Query<&mut Chunk, With<Chunk>>, chunk_info: Res<ChunkInfo>) {
-    chunks.for_each_mut(|mut x| {
-        if x.mesh.is_none() {
-            x.generate_mesh(&chunk_info.id_mapping);
-            //  println!("generate_mesh for {:?}", &x.base_pos_of_chunk);
+/// mesh the chunks whose quad group was updated in place, with the light and blocks of their neighbours
+pub fn generate_mesh(
+    mut chunks: ParamSet<(
+        Query<(Entity, &ChunkState), Changed<ChunkState>>,
+        Query<(&mut Chunk, &mut ChunkState)>,
+    )>,
+    loaded: Res<LoadedChunks>,
+    chunk_info: Res<ChunkInfo>,
+) {
+    let changed = chunks
+        .p0()
+        .iter()
+        .filter(|(_, state)| **state == ChunkState::QuadsBuilt)
+        .map(|(e, _)| e)
+        .collect::<Vec<_>>();
+    let mut chunks = chunks.p1();
+    let mut generated = Vec::new();
+    for e in changed {
+        let chunk = match chunks.get(e) {
+            Ok((c, _)) => c,
+            Err(_) => continue,
+        };
+        let quads = match &chunk.quad_group {
+            Some(q) => q,
+            None => continue,
+        };
+        let neighbours = ChunkNeighbours::from_fn(|face| {
+            let e = loaded.get(LoadedChunks::neighbour_pos(chunk.base_pos_of_chunk, face))?;
+            chunks.get(e).ok().map(|(c, _)| c)
+        });
+        generated.push((
+            e,
+            chunk.build_meshes(
+                quads,
+                &chunk_info.id_mapping,
+                chunk_info.mesh_mode,
+                &neighbours,
+            ),
+        ));
+        //  println!("generate_mesh for {:?}", &x.base_pos_of_chunk);
+    }
+    for (e, (mesh, transparent_mesh, cutout_mesh)) in generated {
+        if let Ok((mut chunk, mut state)) = chunks.get_mut(e) {
+            chunk.mesh = Some(mesh);
+            chunk.transparent_mesh = Some(transparent_mesh);
+            chunk.cutout_mesh = Some(cutout_mesh);
+            *state = ChunkState::Meshed;
         }
-    })
+    }
 }
 
+/// only chunks with an uploaded mesh get the render components
 pub fn insert_material(
-    chunks: Query<Entity, (With<Chunk>, Without<Handle<StandardMaterial>>)>,
+    chunks: Query<
+        Entity,
+        (
+            With<Chunk>,
+            With<Handle<Mesh>>,
+            Without<Handle<ChunkMaterial>>,
+        ),
+    >,
     mut commands: Commands,
     chunk_info: Res<ChunkInfo>,
 ) {
@@ -111,7 +281,7 @@ pub fn insert_material(
 }
 
 pub fn insert_pbr<T: Default + Component>(
-    chunks: Query<Entity, (With<Chunk>, Without<T>)>,
+    chunks: Query<Entity, (With<Chunk>, With<Handle<Mesh>>, Without<T>)>,
     mut commands: Commands,
 ) {
     chunks.for_each(|e| {
@@ -120,19 +290,27 @@ pub fn insert_pbr<T: Default + Component>(
     })
 }
 
+/// upload the meshes of `ChunkState::Meshed` chunks
 pub fn change_mesh(
-    mut chunks: Query<(&mut Chunk, Entity, Option<&Handle<Mesh>>)>,
+    mut chunks: Query<
+        (&mut Chunk, &mut ChunkState, Entity, Option<&Handle<Mesh>>),
+        Changed<ChunkState>,
+    >,
     mut meshs: ResMut<Assets<Mesh>>,
     mut commands: Commands,
 ) {
-    chunks.for_each_mut(|(mut chunk, e, m)| {
-        if !chunk.mesh_up_to_date && chunk.mesh.is_some() {
+    chunks.for_each_mut(|(mut chunk, mut state, e, m)| {
+        if *state == ChunkState::Meshed {
             if let Some(mesh) = chunk.get_bevy_mesh() {
                 if let Some(m) = m {
                     meshs.remove(m);
                 }
-                chunk.mesh_up_to_date = true;
+                *state = ChunkState::Uploaded;
                 if mesh.count_vertices() == 0 {
+                    commands
+                        .entity(e)
+                        .remove::<(Handle<Mesh>, Handle<ChunkMaterial>)>()
+                        .remove::<(Visibility, ComputedVisibility)>();
                     return;
                 }
                 commands.entity(e).insert(meshs.add(mesh));
@@ -141,3 +319,96 @@ pub fn change_mesh(
         };
     });
 }
+
+#[test]
+fn test_track_chunks() {
+    let mut app = App::new();
+    app.init_resource::<LoadedChunks>()
+        .insert_resource(ChunkInfo {
+            id_mapping: default(),
+            material: default(),
+            transparent_material: default(),
+            cutout_material: default(),
+            mesh_mode: default(),
+        })
+        .add_event::<ChunkLoaded>()
+        .add_event::<ChunkUnloaded>()
+        .add_system(track_chunks);
+    let spawn = |app: &mut App, base: Pos, block: Option<Pos>, state: ChunkState| {
+        let mut chunk = Chunk {
+            base_pos_of_chunk: base,
+            quad_group: Some(default()),
+            ..Default::default()
+        };
+        if let Some(p) = block {
+            chunk.blocks.set(p, 1_u64.into());
+        }
+        let e = app.world.spawn((chunk, state)).id();
+        app.update();
+        e
+    };
+    let meshed = |app: &App, e: Entity| {
+        app.world.get::<ChunkState>(e) == Some(&ChunkState::Uploaded)
+            && app.world.get::<Chunk>(e).unwrap().quad_group.is_some()
+    };
+
+    let center = spawn(
+        &mut app,
+        Pos::default(),
+        Some(Pos::from_xyz(5, 5, 5)),
+        ChunkState::Uploaded,
+    );
+    // nothing on the faces touching the center
+    let empty = spawn(
+        &mut app,
+        Pos::from_xyz(16, 0, 0),
+        None,
+        ChunkState::Uploaded,
+    );
+    spawn(
+        &mut app,
+        Pos::from_xyz(0, 0, 16),
+        Some(Pos::from_xyz(3, 3, 15)),
+        ChunkState::Uploaded,
+    );
+    assert!(meshed(&app, center));
+
+    // a block on the face touching the center
+    let above = spawn(
+        &mut app,
+        Pos::from_xyz(0, 16, 0),
+        Some(Pos::from_xyz(3, 0, 3)),
+        ChunkState::Uploaded,
+    );
+    assert_eq!(
+        app.world.get::<ChunkState>(center),
+        Some(&ChunkState::Dirty)
+    );
+    assert!(app.world.get::<Chunk>(center).unwrap().quad_group.is_none());
+
+    // unloading only rebuilds the neighbours it touched
+    *app.world.get_mut::<ChunkState>(center).unwrap() = ChunkState::Uploaded;
+    app.world.get_mut::<Chunk>(center).unwrap().quad_group = Some(default());
+    app.world.despawn(empty);
+    app.update();
+    assert!(meshed(&app, center));
+    app.world.despawn(above);
+    app.update();
+    assert_eq!(
+        app.world.get::<ChunkState>(center),
+        Some(&ChunkState::Dirty)
+    );
+
+    // a chunk that was not meshed yet keeps its state
+    *app.world.get_mut::<ChunkState>(center).unwrap() = ChunkState::Generated;
+    spawn(
+        &mut app,
+        Pos::from_xyz(-16, 0, 0),
+        Some(Pos::from_xyz(15, 0, 0)),
+        ChunkState::Uploaded,
+    );
+    assert_eq!(
+        app.world.get::<ChunkState>(center),
+        Some(&ChunkState::Generated)
+    );
+}
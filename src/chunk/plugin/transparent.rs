@@ -0,0 +1,179 @@
+use bevy::{
+    prelude::*,
+    render::mesh::{Indices, VertexAttributeValues},
+};
+
+use crate::chunk::{blocks::sort_quads_back_to_front, chunk::Chunk, Pos};
+
+use super::{ChunkInfo, ChunkMaterial, ChunkState};
+
+/// the camera has to move this far before every transparent mesh is sorted again
+const SORT_DISTANCE: f32 = 0.5;
+
+/// a child of a chunk entity drawing the transparent mesh of the chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct TransparentMesh {
+    /// the vertices of the mesh are relative to this
+    pub base_pos_of_chunk: Pos,
+}
+
+/// the `TransparentMesh` child of a chunk entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct TransparentMeshEntity(pub Entity);
+
+/// upload the transparent meshes that `change_mesh` left in the chunks
+///
+/// the chunk entity keeps its visibility while it has a transparent child, so the child is drawn
+pub fn change_transparent_mesh(
+    mut chunks: Query<(Entity, &mut Chunk, Option<&TransparentMeshEntity>), Changed<ChunkState>>,
+    children: Query<&Handle<Mesh>, With<TransparentMesh>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    chunk_info: Res<ChunkInfo>,
+    mut commands: Commands,
+) {
+    chunks.for_each_mut(|(e, mut chunk, child)| {
+        let mesh = match chunk.get_transparent_bevy_mesh() {
+            Some(m) => m,
+            None => return,
+        };
+        if let Some(h) = child.and_then(|c| children.get(c.0).ok()) {
+            meshes.remove(h);
+        }
+        if mesh.count_vertices() == 0 {
+            if let Some(child) = child {
+                commands.entity(child.0).despawn_recursive();
+                commands.entity(e).remove::<TransparentMeshEntity>();
+            }
+            return;
+        }
+
+        let handle = meshes.add(mesh);
+        match child {
+            Some(child) => {
+                commands.entity(child.0).insert(handle);
+            }
+            None => {
+                let child = commands
+                    .spawn((
+                        MaterialMeshBundle::<ChunkMaterial> {
+                            mesh: handle,
+                            material: chunk_info.transparent_material.clone(),
+                            ..default()
+                        },
+                        TransparentMesh {
+                            base_pos_of_chunk: chunk.base_pos_of_chunk,
+                        },
+                    ))
+                    .id();
+                commands
+                    .entity(e)
+                    .insert(TransparentMeshEntity(child))
+                    .add_child(child);
+            }
+        }
+        commands
+            .entity(e)
+            .insert((Visibility::default(), ComputedVisibility::default()));
+    });
+}
+
+/// sort the faces of the transparent meshes back to front from the camera,
+/// new meshes right away and all of them once the camera moved `SORT_DISTANCE`
+pub fn sort_transparent_meshes(
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    transparent: Query<(Ref<Handle<Mesh>>, &TransparentMesh)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut sorted_at: Local<Option<Vec3>>,
+) {
+    let eye = match cameras.iter().next() {
+        Some(t) => t.translation(),
+        None => return,
+    };
+    let moved = match *sorted_at {
+        Some(p) => p.distance(eye) >= SORT_DISTANCE,
+        None => true,
+    };
+    if moved {
+        *sorted_at = Some(eye);
+    }
+    for (handle, transparent) in transparent.iter() {
+        if !moved && !handle.is_changed() {
+            continue;
+        }
+        let mesh = match meshes.get_mut(&handle) {
+            Some(m) => m,
+            None => continue,
+        };
+        let mut indices = match mesh.indices() {
+            Some(Indices::U32(i)) => i.clone(),
+            _ => continue,
+        };
+        let vertices = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(v)) => v,
+            _ => continue,
+        };
+        let [x, y, z] = transparent.base_pos_of_chunk.to_array().map(|v| v as f32);
+        let eye = [eye.x - x, eye.y - y, eye.z - z];
+        sort_quads_back_to_front(vertices, &mut indices, eye);
+        mesh.set_indices(Some(Indices::U32(indices)));
+    }
+}
+
+#[test]
+fn test_transparent_mesh() {
+    use super::*;
+    use crate::chunk::blocks::BlockRegistry;
+
+    let registry = BlockRegistry::load("assets/blocks.json").unwrap();
+    let id_mapping = registry.id_mapping();
+    let glass = id_mapping.id_of(registry.get("glass").unwrap()).unwrap();
+    let mut app = test_app(id_mapping);
+    app.world
+        .spawn(Camera3dBundle::default())
+        .insert(GlobalTransform::from_translation(Vec3::new(0.5, 0.5, -10.)));
+
+    // a row of glass along z with a stone at the end
+    let mut chunk = Chunk::default();
+    for z in 0..4 {
+        chunk.set_block(Pos::from_xyz(0, 0, z), glass);
+    }
+    chunk.set_block(Pos::from_xyz(0, 0, 4), 1_u64.into());
+    let e = app.world.spawn(chunk).id();
+    run_until(&mut app, |world| {
+        world.get::<ChunkState>(e) == Some(&ChunkState::Uploaded)
+    });
+    app.update();
+
+    let child = app.world.get::<TransparentMeshEntity>(e).unwrap().0;
+    let meshes = app.world.resource::<Assets<Mesh>>();
+    let mesh = meshes
+        .get(app.world.get::<Handle<Mesh>>(child).unwrap())
+        .unwrap();
+    // the glass faces between two glass blocks and towards the stone are hidden
+    assert_eq!(mesh.count_vertices(), (4 * 4 + 1) * 4);
+    let opaque = meshes
+        .get(app.world.get::<Handle<Mesh>>(e).unwrap())
+        .unwrap();
+    assert_eq!(opaque.count_vertices(), 6 * 4);
+
+    // the furthest face from the camera comes first
+    let vertices = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(v)) => v,
+        _ => panic!(),
+    };
+    let indices = mesh.indices().unwrap().iter().collect::<Vec<_>>();
+    let z = |quad: &[usize]| quad.iter().map(|i| vertices[*i][2]).sum::<f32>() / 6.;
+    let quads = indices.chunks(6).collect::<Vec<_>>();
+    assert!(z(quads[0]) > z(quads[quads.len() - 1]));
+    assert_eq!(z(quads[quads.len() - 1]), 0.);
+
+    // replacing the glass removes the child
+    let mut chunk = app.world.get_mut::<Chunk>(e).unwrap();
+    for z in 0..4 {
+        chunk.set_block(Pos::from_xyz(0, 0, z), 0_u64.into());
+    }
+    run_until(&mut app, |world| {
+        world.get::<TransparentMeshEntity>(e).is_none()
+    });
+    assert!(app.world.get_entity(child).is_none());
+}
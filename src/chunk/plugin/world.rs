@@ -0,0 +1,244 @@
+use std::collections::HashSet;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::chunk::{
+    blocks::BlockId,
+    chunk::{light_chunk, update_light, Chunk, LightChunks},
+    Pos,
+};
+
+use super::{ChunkInfo, ChunkLoaded, ChunkState, LoadedChunks};
+
+type ChunkQuery<'w, 's> = Query<'w, 's, (&'static mut Chunk, Option<&'static mut ChunkState>)>;
+
+/// block access by world position over every loaded chunk
+#[derive(SystemParam)]
+pub struct VoxelWorld<'w, 's> {
+    loaded: Res<'w, LoadedChunks>,
+    chunk_info: Res<'w, ChunkInfo>,
+    chunks: ChunkQuery<'w, 's>,
+}
+
+/// the loaded chunks for the light functions, borrowed apart from the id mapping
+struct LoadedChunkAccess<'a, 'w, 's> {
+    loaded: &'a LoadedChunks,
+    chunks: &'a mut ChunkQuery<'w, 's>,
+}
+
+impl LightChunks for LoadedChunkAccess<'_, '_, '_> {
+    fn chunk(&self, base_pos_of_chunk: Pos) -> Option<&Chunk> {
+        let e = self.loaded.get(base_pos_of_chunk)?;
+        self.chunks.get(e).ok().map(|(c, _)| c)
+    }
+
+    fn chunk_mut(&mut self, base_pos_of_chunk: Pos) -> Option<&mut Chunk> {
+        let e = self.loaded.get(base_pos_of_chunk)?;
+        self.chunks.get_mut(e).ok().map(|(c, _)| c.into_inner())
+    }
+}
+
+impl VoxelWorld<'_, '_> {
+    /// the chunk entity containing this world position
+    pub fn chunk_at(&self, world_pos: Pos) -> Option<Entity> {
+        self.loaded.get(Chunk::base_pos_of(world_pos))
+    }
+
+    /// `None` if the chunk is not loaded
+    pub fn get_block(&self, world_pos: Pos) -> Option<BlockId> {
+        let (chunk, _) = self.chunks.get(self.chunk_at(world_pos)?).ok()?;
+        chunk.get_pos_in_chunk(Chunk::pos_in_chunk_of(world_pos))
+    }
+
+    /// set a block, the chunk and the neighbours sharing the edited border are remeshed
+    ///
+    /// the quad group of the chunk is updated in place, the neighbours are rebuilt,
+    /// the chunks whose light changed are meshed again by a meshing job
+    ///
+    /// returns the previous block, or `None` if the chunk is not loaded
+    pub fn set_block(&mut self, world_pos: Pos, id: BlockId) -> Option<BlockId> {
+        let e = self.chunk_at(world_pos)?;
+        let pos = Chunk::pos_in_chunk_of(world_pos);
+        let outside = Chunk::border_faces(pos)
+            .map(|face| (pos + face, self.get_block(world_pos + face)))
+            .collect::<Vec<_>>();
+
+        let (mut chunk, state) = self.chunks.get_mut(e).ok()?;
+        let old = chunk.set_block_incremental(pos, id, &self.chunk_info.id_mapping, |p| {
+            outside.iter().find(|(q, _)| *q == p).and_then(|(_, b)| *b)
+        })?;
+        if old == id {
+            return Some(old);
+        }
+        if let Some(mut state) = state {
+            *state = if chunk.quad_group.is_some() {
+                ChunkState::QuadsBuilt
+            } else {
+                ChunkState::Dirty
+            };
+        }
+
+        let base = chunk.base_pos_of_chunk;
+        for face in Chunk::border_faces(pos) {
+            let e = match self.loaded.get(LoadedChunks::neighbour_pos(base, face)) {
+                Some(e) => e,
+                None => continue,
+            };
+            if let Ok((mut c, state)) = self.chunks.get_mut(e) {
+                c.mark_dirty();
+                if let Some(mut state) = state {
+                    *state = ChunkState::Dirty;
+                }
+            }
+        }
+
+        let mut access = LoadedChunkAccess {
+            loaded: &self.loaded,
+            chunks: &mut self.chunks,
+        };
+        let changed = update_light(&mut access, &self.chunk_info.id_mapping, world_pos);
+        self.remesh_lit(changed);
+        Some(old)
+    }
+
+    /// light a newly loaded chunk, the chunks whose light changed are remeshed
+    pub fn light_chunk(&mut self, base_pos_of_chunk: Pos) {
+        let mut access = LoadedChunkAccess {
+            loaded: &self.loaded,
+            chunks: &mut self.chunks,
+        };
+        let changed = light_chunk(&mut access, &self.chunk_info.id_mapping, base_pos_of_chunk);
+        self.remesh_lit(changed);
+    }
+
+    /// send these chunks back to `start_meshing_jobs`, which builds their meshes from the quad groups they keep,
+    /// a meshing job that is still running is started again by `finish_meshing_jobs`
+    fn remesh_lit(&mut self, changed: HashSet<Pos>) {
+        for base in changed {
+            let e = match self.loaded.get(base) {
+                Some(e) => e,
+                None => continue,
+            };
+            if let Ok((mut chunk, state)) = self.chunks.get_mut(e) {
+                chunk.mark_mesh_dirty();
+                if let Some(mut state) = state {
+                    let built = matches!(
+                        *state,
+                        ChunkState::QuadsBuilt | ChunkState::Meshed | ChunkState::Uploaded
+                    );
+                    if built && chunk.quad_group.is_some() {
+                        *state = ChunkState::Dirty;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// light the chunks loaded this frame before they are meshed
+pub fn light_loaded_chunks(mut loaded_events: EventReader<ChunkLoaded>, mut world: VoxelWorld) {
+    for event in loaded_events.iter() {
+        world.light_chunk(event.base_pos_of_chunk);
+    }
+}
+
+#[test]
+fn test_voxel_world() {
+    use bevy::ecs::system::SystemState;
+
+    let mut world = World::new();
+    let base = Pos::from_xyz(-16, -16, -16);
+    let e = world
+        .spawn((
+            Chunk {
+                base_pos_of_chunk: base,
+                ..Default::default()
+            },
+            ChunkState::Uploaded,
+        ))
+        .id();
+    let neighbour_base = Pos::from_xyz(0, -16, -16);
+    let neighbour = world
+        .spawn((
+            Chunk {
+                base_pos_of_chunk: neighbour_base,
+                quad_group: Some(default()),
+                ..Default::default()
+            },
+            ChunkState::Uploaded,
+        ))
+        .id();
+    let mut loaded = LoadedChunks::default();
+    loaded.insert(base, e);
+    loaded.insert(neighbour_base, neighbour);
+    world.insert_resource(loaded);
+    world.insert_resource(ChunkInfo {
+        id_mapping: default(),
+        material: default(),
+        transparent_material: default(),
+        cutout_material: default(),
+        mesh_mode: default(),
+    });
+
+    let mut state: SystemState<VoxelWorld> = SystemState::new(&mut world);
+    let mut voxel_world = state.get_mut(&mut world);
+    let p = Pos::from_xyz(-1, -16, -5);
+    assert_eq!(voxel_world.chunk_at(p), Some(e));
+    assert_eq!(voxel_world.chunk_at(Pos::from_xyz(0, 0, 0)), None);
+    assert_eq!(voxel_world.get_block(p), Some(0_u64.into()));
+    assert_eq!(voxel_world.set_block(p, 1_u64.into()), Some(0_u64.into()));
+    assert_eq!(voxel_world.get_block(p), Some(1_u64.into()));
+    assert_eq!(
+        voxel_world.set_block(Pos::from_xyz(0, 0, 0), 1_u64.into()),
+        None
+    );
+
+    let chunk = world.get::<Chunk>(e).unwrap();
+    assert_eq!(chunk.blocks[Pos::from_xyz(15, 0, 11)], 1_u64.into());
+    assert_eq!(world.get::<ChunkState>(e), Some(&ChunkState::Dirty));
+    assert!(world.get::<Chunk>(neighbour).unwrap().quad_group.is_none());
+    assert_eq!(world.get::<ChunkState>(neighbour), Some(&ChunkState::Dirty));
+}
+
+#[test]
+fn test_light_loaded_chunks() {
+    use bevy::ecs::system::SystemState;
+
+    let mut app = super::test_app(default());
+
+    let e = app
+        .world
+        .spawn((
+            Chunk {
+                quad_group: Some(default()),
+                open_sky: Some(Box::new([[true; 16]; 16])),
+                ..Default::default()
+            },
+            ChunkState::Uploaded,
+        ))
+        .id();
+    app.update();
+    let light = |world: &World, p: Pos| {
+        world
+            .get::<Chunk>(e)
+            .unwrap()
+            .light
+            .as_ref()
+            .unwrap()
+            .get(p)
+    };
+    assert_eq!(light(&app.world, Pos::from_xyz(4, 0, 4)), (15, 0));
+
+    // a block on top shades the blocks below it, which are meshed again by a job
+    let mut state: SystemState<VoxelWorld> = SystemState::new(&mut app.world);
+    let mut voxel_world = state.get_mut(&mut app.world);
+    voxel_world.set_block(Pos::from_xyz(4, 15, 4), 1_u64.into());
+    assert_eq!(light(&app.world, Pos::from_xyz(4, 0, 4)), (14, 0));
+    assert_eq!(light(&app.world, Pos::from_xyz(5, 0, 4)), (15, 0));
+    assert_eq!(app.world.get::<ChunkState>(e), Some(&ChunkState::Dirty));
+    assert!(app.world.get::<Chunk>(e).unwrap().quad_group.is_some());
+    super::run_until(&mut app, |world| {
+        world.get::<ChunkState>(e) == Some(&ChunkState::Uploaded)
+    });
+    assert!(app.world.resource::<super::MeshingJobs>().is_empty());
+}
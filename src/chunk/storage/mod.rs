@@ -0,0 +1,385 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bevy::prelude::{warn, Resource};
+
+use super::{
+    blocks::{BlockId, BlockRegistry, BlockType, IdMapping, Missing},
+    chunk::Chunk,
+    Pos,
+};
+
+mod region;
+pub use region::*;
+
+const WORLD_FILE: &str = "world.json";
+const REGION_DIR: &str = "region";
+
+/// the chunks saved in a world directory, a region is read the first time one of its chunks is used
+/// and then kept compressed in memory
+///
+/// the ids of the saved chunks are the ids of `id_mapping`,
+/// the save stores the name of the block of every id so they can be remapped when it is loaded
+#[derive(Debug, Clone, Default, Resource)]
+pub struct WorldSave {
+    dir: PathBuf,
+    pub id_mapping: IdMapping,
+    /// the new id of every id in the region files and the id of unknown ids,
+    /// `None` if the ids are the same
+    remap: Option<Arc<(Vec<BlockId>, BlockId)>>,
+    regions: HashMap<Pos, Region>,
+    /// the regions that have to be written again
+    changed: HashSet<Pos>,
+}
+
+fn region_path(dir: &Path, region: Pos) -> PathBuf {
+    dir.join(REGION_DIR).join(format!(
+        "r.{}.{}.{}.region",
+        region.x(),
+        region.y(),
+        region.z()
+    ))
+}
+
+/// the region position in a file name from `region_path`
+fn parse_region_name(name: &str) -> Option<Pos> {
+    let mut v = name
+        .strip_prefix("r.")?
+        .strip_suffix(".region")?
+        .split('.')
+        .map(|v| v.parse::<i64>().ok());
+    let pos = Pos::from_xyz(v.next()??, v.next()??, v.next()??);
+    match v.next() {
+        Some(_) => None,
+        None => Some(pos),
+    }
+}
+
+/// reads a region file of a `WorldSave` and remaps its ids, without the save so it can run in a task
+#[derive(Debug, Clone)]
+pub struct RegionReader {
+    path: PathBuf,
+    remap: Option<Arc<(Vec<BlockId>, BlockId)>>,
+}
+
+impl RegionReader {
+    /// an empty region if there is no file
+    pub fn read(&self) -> io::Result<Region> {
+        let mut r = match fs::File::open(&self.path) {
+            Ok(f) => Region::read_from(&mut BufReader::new(f))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Region::default()),
+            Err(e) => return Err(e),
+        };
+        if let Some(remap) = &self.remap {
+            let (remap, missing) = remap.as_ref();
+            r.map_ids(|id| remap.get(usize::from(id)).copied().unwrap_or(*missing))?;
+        }
+        Ok(r)
+    }
+}
+
+/// write to a temporary file first so a failed write keeps the old file
+fn write_file<F>(path: &Path, f: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<fs::File>) -> io::Result<()>,
+{
+    let tmp = path.with_extension("tmp");
+    let mut w = BufWriter::new(fs::File::create(&tmp)?);
+    f(&mut w)?;
+    w.flush()?;
+    drop(w);
+    fs::rename(tmp, path)
+}
+
+impl WorldSave {
+    /// an empty save written to `dir`
+    pub fn new(dir: impl Into<PathBuf>, id_mapping: IdMapping) -> WorldSave {
+        WorldSave {
+            dir: dir.into(),
+            id_mapping,
+            ..Default::default()
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// the region, read from its file and remapped the first time
+    fn region(&mut self, region: Pos) -> io::Result<&mut Region> {
+        if !self.regions.contains_key(&region) {
+            let r = self.region_reader(region).read()?;
+            self.insert_region(region, r);
+        }
+        Ok(self.regions.get_mut(&region).unwrap())
+    }
+
+    /// the region is in memory, its chunks are used without reading from disk
+    pub fn has_region(&self, region: Pos) -> bool {
+        self.regions.contains_key(&region)
+    }
+
+    /// reads the region from disk, `insert_region` takes what it read
+    pub fn region_reader(&self, region: Pos) -> RegionReader {
+        RegionReader {
+            path: region_path(&self.dir, region),
+            remap: self.remap.clone(),
+        }
+    }
+
+    /// a region from `region_reader`, ignored if the region was read in the meantime
+    pub fn insert_region(&mut self, region: Pos, r: Region) {
+        if self.regions.contains_key(&region) {
+            return;
+        }
+        // written again with the ids of `id_mapping`
+        if self.remap.is_some() && !r.is_empty() {
+            self.changed.insert(region);
+        }
+        self.regions.insert(region, r);
+    }
+
+    pub fn contains(&mut self, base_pos_of_chunk: Pos) -> io::Result<bool> {
+        Ok(self.get_compressed(base_pos_of_chunk)?.is_some())
+    }
+
+    /// the data from `compress_chunk`, `decompress_chunk` turns it back into a chunk
+    pub fn get_compressed(&mut self, base_pos_of_chunk: Pos) -> io::Result<Option<&[u8]>> {
+        Ok(self
+            .region(region_of(base_pos_of_chunk))?
+            .get(base_pos_of_chunk))
+    }
+
+    pub fn load_chunk(&mut self, base_pos_of_chunk: Pos) -> io::Result<Option<Chunk>> {
+        match self.get_compressed(base_pos_of_chunk)? {
+            Some(data) => decompress_chunk(base_pos_of_chunk, data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// store a chunk in memory, `write` puts it on disk
+    pub fn insert(&mut self, chunk: &Chunk) -> io::Result<()> {
+        let region = region_of(chunk.base_pos_of_chunk);
+        self.region(region)?.insert(chunk);
+        self.changed.insert(region);
+        Ok(())
+    }
+
+    /// how many regions have been read or written so far
+    pub fn regions_in_memory(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// write the id mapping, by the names in `registry`, and the regions changed since the last write
+    ///
+    /// the regions not read yet are read first if their ids have to be remapped,
+    /// as the written id mapping no longer matches them
+    pub fn write(&mut self, registry: &BlockRegistry) -> io::Result<()> {
+        if self.remap.is_some() {
+            let region_dir = self.dir.join(REGION_DIR);
+            if region_dir.exists() {
+                for entry in fs::read_dir(region_dir)? {
+                    if let Some(region) = entry?.file_name().to_str().and_then(parse_region_name) {
+                        self.region(region)?;
+                    }
+                }
+            }
+        }
+        let names = self
+            .id_mapping
+            .mapping
+            .iter()
+            .map(|b| {
+                registry.name_of(*b).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{b:?} is not in the block registry"),
+                    )
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        fs::create_dir_all(self.dir.join(REGION_DIR))?;
+        write_file(&self.dir.join(WORLD_FILE), |w| {
+            serde_json::to_writer_pretty(w, &serde_json::json!({ "id_mapping": names }))
+                .map_err(io::Error::from)
+        })?;
+
+        for region in self.changed.drain() {
+            let r = &self.regions[&region];
+            write_file(&region_path(&self.dir, region), |w| r.write_to(w))?;
+        }
+        self.remap = None;
+        Ok(())
+    }
+}
+
+/// open the world saved in `dir`, the regions read from it have the saved ids changed to the ids in `id_mapping`
+/// of the blocks with the same name in `registry`
+///
+/// blocks that are not in `registry` or `id_mapping` become `BlockType::Missing`, which has to be in `id_mapping`
+pub fn load_world(
+    dir: impl AsRef<Path>,
+    registry: &BlockRegistry,
+    id_mapping: &IdMapping,
+) -> io::Result<WorldSave> {
+    let dir = dir.as_ref();
+    let world: serde_json::Value =
+        serde_json::from_reader(BufReader::new(fs::File::open(dir.join(WORLD_FILE))?))?;
+    let names = world["id_mapping"]
+        .as_array()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no id mapping"))?;
+    let missing = id_mapping
+        .id_of(BlockType::Missing(Missing))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no id for missing blocks"))?;
+    // the new id of every saved id
+    let mut remap = Vec::with_capacity(names.len());
+    for name in names {
+        let id = name
+            .as_str()
+            .and_then(|name| registry.get(name))
+            .and_then(|block| id_mapping.id_of(block));
+        remap.push(id.unwrap_or_else(|| {
+            warn!("the saved block {name} is not registered, it is replaced by a placeholder");
+            missing
+        }));
+    }
+    let unchanged = remap
+        .iter()
+        .enumerate()
+        .all(|(i, id)| *id == BlockId::from(i));
+
+    let mut save = WorldSave::new(dir, id_mapping.clone());
+    if !unchanged {
+        save.remap = Some(Arc::new((remap, missing)));
+    }
+    Ok(save)
+}
+
+/// store the chunks in the save and write it to disk
+pub fn save_world<'a, I>(
+    save: &mut WorldSave,
+    chunks: I,
+    registry: &BlockRegistry,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a Chunk>,
+{
+    for chunk in chunks {
+        save.insert(chunk)?;
+    }
+    save.write(registry)
+}
+
+#[test]
+fn test_save_and_load_world() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let dir = std::env::temp_dir().join(format!("phyvox_test_world_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut r = StdRng::seed_from_u64(16);
+    let mut chunks = Vec::new();
+    for base in [
+        Pos::from_xyz(0, 0, 0),
+        Pos::from_xyz(-16, -16, -16),
+        Pos::from_xyz(-512, 0, 496),
+        Pos::from_xyz(-528, -1024, 512),
+        Pos::from_xyz(4096, -16, -8192),
+    ] {
+        let mut c = Chunk {
+            base_pos_of_chunk: base,
+            ..Default::default()
+        };
+        for _ in 0..200 {
+            let p = Pos::from_xyz(r.gen_range(0..16), r.gen_range(0..16), r.gen_range(0..16));
+            c.set_block(p, r.gen_range(0..2_u64).into());
+        }
+        chunks.push(c);
+    }
+
+    let registry = BlockRegistry::default();
+    let mut save = WorldSave::new(&dir, IdMapping::default());
+    save_world(&mut save, &chunks[..3], &registry).unwrap();
+    // a second save only writes the regions it changed
+    save_world(&mut save, &chunks[3..], &registry).unwrap();
+
+    let mut loaded = load_world(&dir, &registry, &IdMapping::default()).unwrap();
+    assert_eq!(loaded.id_mapping, IdMapping::default());
+    // the regions are only read when their chunks are used
+    assert_eq!(loaded.regions_in_memory(), 0);
+    for c in chunks.iter() {
+        let l = loaded.load_chunk(c.base_pos_of_chunk).unwrap().unwrap();
+        assert_eq!(l.blocks.to_array(), c.blocks.to_array());
+        assert!(!l.modified);
+    }
+    assert_eq!(loaded.regions_in_memory(), 5);
+    assert!(loaded
+        .load_chunk(Pos::from_xyz(-16, 0, 0))
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        parse_region_name("r.-1.0.-2.region"),
+        Some(Pos::from_xyz(-1, 0, -2))
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(load_world(&dir, &registry, &IdMapping::default()).is_err());
+}
+
+#[test]
+fn test_remap_block_ids() {
+    let dir = std::env::temp_dir().join(format!("phyvox_test_remap_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let registry = |names: &[&str]| {
+        let blocks = names
+            .iter()
+            .map(|n| format!(r#"{{ "name": "{n}", "visibility": "Empty" }}"#))
+            .collect::<Vec<_>>()
+            .join(",");
+        BlockRegistry::from_json(&format!(r#"{{ "blocks": [{blocks}] }}"#)).unwrap()
+    };
+
+    // a is 3 and b is 4 when saved
+    let old = registry(&["a", "b"]);
+    let mut chunk = Chunk::new_filled_with_id(1_u64.into());
+    chunk.set_block(Pos::from_xyz(0, 0, 0), 3_u64.into());
+    chunk.set_block(Pos::from_xyz(0, 0, 1), 4_u64.into());
+    let mut save = WorldSave::new(&dir, old.id_mapping());
+    save_world(&mut save, [&chunk], &old).unwrap();
+
+    // b is 3 and c is 4 when loaded, a is gone
+    let new = registry(&["b", "c"]);
+    let mut loaded = load_world(&dir, &new, &new.id_mapping()).unwrap();
+    assert_eq!(loaded.id_mapping, new.id_mapping());
+    let c = loaded.load_chunk(Pos::default()).unwrap().unwrap();
+    let block_at = |p| {
+        c.blocks[p]
+            .get_block_type(&loaded.id_mapping)
+            .and_then(|b| new.name_of(b))
+    };
+    assert_eq!(block_at(Pos::from_xyz(0, 0, 0)), Some("phyvox:missing"));
+    assert_eq!(block_at(Pos::from_xyz(0, 0, 1)), Some("phyvox:b"));
+    assert_eq!(block_at(Pos::from_xyz(0, 1, 0)), Some("phyvox:stone"));
+
+    // the remapped regions are written with the new names
+    loaded.write(&new).unwrap();
+    let mut again = load_world(&dir, &new, &new.id_mapping()).unwrap();
+    let a = again.load_chunk(Pos::default()).unwrap().unwrap();
+    assert_eq!(a.blocks.to_array(), c.blocks.to_array());
+
+    // names saved without a namespace are in the default one
+    fs::write(
+        dir.join(WORLD_FILE),
+        r#"{ "id_mapping": ["none", "stone", "missing", "b"] }"#,
+    )
+    .unwrap();
+    let mut legacy = load_world(&dir, &new, &new.id_mapping()).unwrap();
+    let l = legacy.load_chunk(Pos::default()).unwrap().unwrap();
+    assert_eq!(l.blocks.to_array(), c.blocks.to_array());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::chunk::{
+    blocks::BlockId,
+    chunk::{Chunk, ChunkBlocks, CHUNK_SIZE},
+    Pos,
+};
+
+/// chunks along each side of a region
+pub const REGION_SIZE: i64 = 32;
+
+const MAGIC: &[u8; 4] = b"PVXR";
+const VERSION: u32 = 1;
+/// the runs of a chunk take at most 12 bytes a block, deflate adds a few bytes to that
+const MAX_CHUNK_LEN: usize = 1 << 16;
+
+/// the position of the region containing a chunk, in regions
+pub fn region_of(base_pos_of_chunk: Pos) -> Pos {
+    let size = REGION_SIZE * CHUNK_SIZE as i64;
+    Pos::from_xyz(
+        base_pos_of_chunk.x().div_euclid(size),
+        base_pos_of_chunk.y().div_euclid(size),
+        base_pos_of_chunk.z().div_euclid(size),
+    )
+}
+
+/// the compressed chunks of one region, by base position
+///
+/// a region file is the magic, the format version and the chunk count,
+/// then the base position, the length and the compressed blocks of every chunk
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Region {
+    chunks: HashMap<Pos, Vec<u8>>,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_array<const N: usize, R: Read>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(r)?))
+}
+
+fn read_i64<R: Read>(r: &mut R) -> io::Result<i64> {
+    Ok(i64::from_le_bytes(read_array(r)?))
+}
+
+/// the blocks as `ChunkBlocks::to_rle`, compressed with deflate
+pub fn compress_chunk(chunk: &Chunk) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&chunk.blocks.to_rle())
+        .and_then(|_| encoder.finish())
+        .expect("writing to a Vec does not fail")
+}
+
+/// the unmodified chunk at `base_pos_of_chunk` from `compress_chunk` data
+pub fn decompress_chunk(base_pos_of_chunk: Pos, data: &[u8]) -> io::Result<Chunk> {
+    let mut rle = Vec::new();
+    DeflateDecoder::new(data).read_to_end(&mut rle)?;
+    let blocks = ChunkBlocks::from_rle(&rle)
+        .ok_or_else(|| invalid_data("the runs of the chunk do not cover it"))?;
+    Ok(Chunk {
+        base_pos_of_chunk,
+        blocks,
+        ..Default::default()
+    })
+}
+
+impl Region {
+    pub fn get(&self, base_pos_of_chunk: Pos) -> Option<&[u8]> {
+        self.chunks.get(&base_pos_of_chunk).map(|v| v.as_slice())
+    }
+
+    pub fn insert(&mut self, chunk: &Chunk) {
+        self.chunks
+            .insert(chunk.base_pos_of_chunk, compress_chunk(chunk));
+    }
+
+    /// replace every id of every chunk with `f(id)`
+    pub fn map_ids<F: FnMut(BlockId) -> BlockId>(&mut self, mut f: F) -> io::Result<()> {
+        for (pos, data) in self.chunks.iter_mut() {
+            let mut chunk = decompress_chunk(*pos, data)?;
+            chunk.blocks.map_ids(&mut f);
+            *data = compress_chunk(&chunk);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&(self.chunks.len() as u32).to_le_bytes())?;
+        for (pos, data) in self.chunks.iter() {
+            for v in pos.to_array() {
+                w.write_all(&v.to_le_bytes())?;
+            }
+            w.write_all(&(data.len() as u32).to_le_bytes())?;
+            w.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Region> {
+        if &read_array::<4, _>(r)? != MAGIC {
+            return Err(invalid_data("not a region file"));
+        }
+        if read_u32(r)? != VERSION {
+            return Err(invalid_data("unknown region file version"));
+        }
+        let count = read_u32(r)?;
+        let mut region = Region::default();
+        for _ in 0..count {
+            let pos = Pos::from_array([read_i64(r)?, read_i64(r)?, read_i64(r)?]);
+            let len = read_u32(r)? as usize;
+            if len > MAX_CHUNK_LEN {
+                return Err(invalid_data("a chunk is longer than any chunk can be"));
+            }
+            let mut data = Vec::new();
+            r.take(len as u64).read_to_end(&mut data)?;
+            if data.len() != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            region.chunks.insert(pos, data);
+        }
+        Ok(region)
+    }
+}
+
+#[test]
+fn test_compress_chunk() {
+    let base = Pos::from_xyz(-32, 16, -512);
+    let filled = Chunk::new_filled_with_id(3_u64.into());
+    let c = decompress_chunk(base, &compress_chunk(&filled)).unwrap();
+    assert_eq!(c.blocks, ChunkBlocks::Single(3_u64.into()));
+    assert_eq!(c.base_pos_of_chunk, base);
+
+    for n in [2_u64, 300] {
+        let mut chunk = Chunk::default();
+        for i in 0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
+            let pos = crate::chunk::chunk::pos_of_index(i);
+            chunk.blocks.set(pos, (i as u64 * 7 % n).into());
+        }
+        let c = decompress_chunk(base, &compress_chunk(&chunk)).unwrap();
+        assert_eq!(c.blocks.to_array(), chunk.blocks.to_array());
+    }
+    assert!(decompress_chunk(base, &[1, 2, 3]).is_err());
+}
+
+#[test]
+fn test_region_file() {
+    assert_eq!(
+        region_of(Pos::from_xyz(0, 496, -16)),
+        Pos::from_xyz(0, 0, -1)
+    );
+    assert_eq!(
+        region_of(Pos::from_xyz(-512, 512, -528)),
+        Pos::from_xyz(-1, 1, -2)
+    );
+
+    let mut region = Region::default();
+    let mut chunk = Chunk {
+        base_pos_of_chunk: Pos::from_xyz(-16, -32, -48),
+        ..Default::default()
+    };
+    chunk.set_block(Pos::from_xyz(1, 2, 3), 1_u64.into());
+    region.insert(&chunk);
+    region.insert(&Chunk::new_filled_with_id(1_u64.into()));
+
+    let mut file = Vec::new();
+    region.write_to(&mut file).unwrap();
+    let read = Region::read_from(&mut file.as_slice()).unwrap();
+    assert_eq!(read, region);
+    let c = decompress_chunk(
+        chunk.base_pos_of_chunk,
+        read.get(chunk.base_pos_of_chunk).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(c.blocks[Pos::from_xyz(1, 2, 3)], 1_u64.into());
+
+    // cut off in the middle of a chunk
+    assert!(Region::read_from(&mut &file[..file.len() - 1]).is_err());
+    let mut huge = file[..12].to_vec();
+    huge.extend_from_slice(&[0; 24]);
+    huge.extend_from_slice(&u32::MAX.to_le_bytes());
+    assert!(Region::read_from(&mut huge.as_slice()).is_err());
+
+    file[0] = b'X';
+    assert!(Region::read_from(&mut file.as_slice()).is_err());
+}
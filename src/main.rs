@@ -14,7 +14,7 @@ use bevy::{
 use bevy_flycam::prelude::*;
 use phyvox::{
     chunk::{
-        blocks::IdMapping,
+        blocks::{BlockAtlas, BlockRegistry, IdMapping},
         chunk::{simple_generator::SimpleGenerator, Chunk, ChunkGenerator, Seed},
         generator_plugin::ChunkGeneratorPlugin,
         Pos,
@@ -31,7 +31,7 @@ fn main() {
             //let mut c = Chunk::new_filled_with_id(1_u64.into());
             let mut c = Chunk::default();
             let id_mapping = IdMapping::default();
-            c.blocks[0][0][0] = 1_u64.into();
+            c.blocks.set(Pos::from_xyz(0, 0, 0), 1_u64.into());
             c.generate_quad_group(&id_mapping);
             c.generate_mesh(&id_mapping);
             black_box(c.get_bevy_mesh().unwrap());
@@ -39,15 +39,19 @@ fn main() {
     });
     //return;
 
+    let registry = BlockRegistry::load("assets/blocks.json").unwrap();
+    let atlas = BlockAtlas::load("assets/textures", &registry).unwrap();
     App::new()
         .add_plugins(DefaultPlugins)
+        .insert_resource(registry)
+        .insert_resource(atlas)
         .add_plugin(PlayerPlugin)
         .add_plugin(phyvox::chunk::plugin::ChunkPlugin)
         //.add_plugin(ControllerPlugin)
         // .add_plugin(DronePlugin)
         .add_plugin(TestPlugin)
         .add_plugin(bevy_inspector_egui::quick::WorldInspectorPlugin::new())
-        .add_plugin(ChunkGeneratorPlugin)
+        .add_plugin(ChunkGeneratorPlugin::default())
         .add_startup_system(setup)
         //.add_system(sleep)
         //.add_system(frame_time)
@@ -113,9 +117,9 @@ fn setup(
                 //let mut c = Chunk::new_filled_with_id(1_u64.into());
                 let mut c =
                     SimpleGenerator.generate_chunk(Pos::from_xyz(0, 0, 0), Seed { seed: 0 });
-                c.blocks[0][0][0] = 0_u64.into();
-                c.blocks[Pos::from_xyz(5, 0, 4)] = 0_u64.into();
-                c.blocks[Pos::from_xyz(8, 1, 9)] = 1_u64.into();
+                c.blocks.set(Pos::from_xyz(0, 0, 0), 0_u64.into());
+                c.blocks.set(Pos::from_xyz(5, 0, 4), 0_u64.into());
+                c.blocks.set(Pos::from_xyz(8, 1, 9), 1_u64.into());
                 c
             })
             .insert(Transform::from_xyz(3.0, 3.0, 3.0));
@@ -128,9 +132,9 @@ fn setup(
                     let mut c =
                         SimpleGenerator.generate_chunk(Pos::from_xyz(0, 0, 0), Seed { seed: 0 });
                     let id_mapping = run_with_time(|| IdMapping::default());
-                    c.blocks[0][0][0] = 0_u64.into();
-                    c.blocks[Pos::from_xyz(5, 0, 4)] = 0_u64.into();
-                    c.blocks[Pos::from_xyz(8, 1, 9)] = 1_u64.into();
+                    c.blocks.set(Pos::from_xyz(0, 0, 0), 0_u64.into());
+                    c.blocks.set(Pos::from_xyz(5, 0, 4), 0_u64.into());
+                    c.blocks.set(Pos::from_xyz(8, 1, 9), 1_u64.into());
                     run_with_time(|| c.generate_quad_group(&id_mapping));
                     run_with_time(|| c.generate_mesh(&id_mapping));
                     run_with_time(|| c.get_bevy_mesh().unwrap())
@@ -320,6 +324,20 @@ fn t() {
     //c.get_bevy_mesh().unwrap();
 }
 
+#[test]
+fn compare_mesh_mode() {
+    use phyvox::chunk::blocks::MeshMode;
+
+    let id_mapping = IdMapping::default();
+    let vertices = [MeshMode::Naive, MeshMode::Greedy].map(|mode| {
+        let mut c = SimpleGenerator.generate_chunk(Pos::from_xyz(0, 0, 0), Seed { seed: 7 });
+        c.generate_quad_group(&id_mapping);
+        run_with_time(|| c.generate_mesh_with_mode(&id_mapping, mode));
+        c.mesh.as_ref().unwrap().vertices.len()
+    });
+    assert!(vertices[1] < vertices[0]);
+}
+
 fn run_with_time<F, T>(f: F) -> T
 where
     F: FnOnce() -> T,